@@ -1,13 +1,14 @@
-//! This crate provides one function: `fastchr`, which very quickly finds the first occurrence of a given byte in a slice.
-//! `fastchr` is implemented using SIMD intrinsics and runtime CPU feature detection so it will always use the fastest method available
-//! on a platform. If SIMD features are not available, `fastchr` falls back to using `memchr`.
+//! This crate provides `fastchr`, which very quickly finds the first occurrence of a given byte in a slice, and `fastmem`,
+//! which does the same for a given byte string.
+//! Both are implemented using SIMD intrinsics and runtime CPU feature detection so they will always use the fastest method
+//! available on a platform. If SIMD features are not available, they fall back to using `memchr`.
 
 #![warn(missing_docs)]
 #![feature(stdsimd)]
 
 extern crate memchr;
 
-use memchr::memchr;
+use memchr::{memchr, memrchr};
 
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
@@ -372,3 +373,164 @@ unsafe fn avx_fastchr(needle: u8, haystack: &[u8]) -> Option<usize> {
 
     None
 }
+
+/// Returns the index corresponding to the first occurrence of `needle` in `haystack`, or `None` if one is not found.
+/// An empty `needle` is found at index 0.
+/// 
+/// Like `fastchr`, `fastmem` uses SIMD intrinsics where they are available. Candidate positions are found by comparing
+/// the first and last bytes of `needle` against a whole lane of `haystack` at once, so only positions where both match
+/// are compared in full.
+/// 
+/// # Example
+/// 
+/// ```
+/// use fastchr::fastmem;
+/// 
+/// let haystack = b"the quick brown fox jumps over the lazy dog";
+/// assert_eq!(fastmem(b"the", haystack), Some(0));
+/// assert_eq!(fastmem(b"the lazy", haystack), Some(31));
+/// assert_eq!(fastmem(b"the fox", haystack), None);
+/// ```
+#[inline]
+pub fn fastmem(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    match needle.len() {
+        0 => Some(0),
+        1 => fastchr(needle[0], haystack),
+        n if n > haystack.len() => None,
+        _ => simd_fastmem(needle, haystack),
+    }
+}
+
+/// Returns the index corresponding to the last occurrence of `needle` in `haystack`, or `None` if one is not found.
+/// An empty `needle` is found at the end of `haystack`.
+/// 
+/// # Example
+/// 
+/// ```
+/// use fastchr::fastrmem;
+/// 
+/// let haystack = b"the quick brown fox jumps over the lazy dog";
+/// assert_eq!(fastrmem(b"the", haystack), Some(31));
+/// assert_eq!(fastrmem(b"the fox", haystack), None);
+/// ```
+pub fn fastrmem(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    let (first, rest) = match needle.split_first() {
+        Some(split) => split,
+        None => return Some(haystack.len()),
+    };
+    if needle.len() > haystack.len() {
+        return None;
+    }
+
+    let mut end = haystack.len() - rest.len();
+    while let Some(i) = memrchr(*first, &haystack[..end]) {
+        if &haystack[i + 1..i + needle.len()] == rest {
+            return Some(i)
+        }
+        end = i;
+    }
+    None
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline]
+fn simd_fastmem(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    if is_x86_feature_detected!("avx2") {
+        unsafe { avx_fastmem(needle, haystack) }
+    } else if is_x86_feature_detected!("sse2") {
+        unsafe { sse_fastmem(needle, haystack) }
+    } else {
+        fallback_fastmem(needle, haystack, 0)
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+#[inline]
+fn simd_fastmem(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    fallback_fastmem(needle, haystack, 0)
+}
+
+/// Searches for `needle` in `haystack` starting at `index`, comparing in full wherever the first byte matches.
+fn fallback_fastmem(needle: &[u8], haystack: &[u8], mut index: usize) -> Option<usize> {
+    let last_start = haystack.len() - needle.len();
+    while index <= last_start {
+        match memchr(needle[0], &haystack[index..last_start + 1]) {
+            Some(u) => {
+                let candidate = index + u;
+                if &haystack[candidate..candidate + needle.len()] == needle {
+                    return Some(candidate)
+                }
+                index = candidate + 1;
+            }
+            None => return None,
+        }
+    }
+    None
+}
+
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn sse_fastmem(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+
+    let last_offset = needle.len() - 1;
+    let ptr = haystack.as_ptr() as usize;
+    let mut index: usize = 0;
+
+    // Read two 16 byte lanes at a time, one where the needle would start and one where it would end, and check where
+    // both the first and last bytes of the needle match.
+    let wide_first = _mm_set1_epi8(needle[0] as i8);
+    let wide_last = _mm_set1_epi8(needle[last_offset] as i8);
+
+    while index + last_offset + SSE_LANE_WIDTH <= haystack.len() {
+        let hay_first = _mm_loadu_si128((ptr + index) as *const __m128i);
+        let hay_last = _mm_loadu_si128((ptr + index + last_offset) as *const __m128i);
+        let hay_cmp = _mm_and_si128(_mm_cmpeq_epi8(hay_first, wide_first), _mm_cmpeq_epi8(hay_last, wide_last));
+        let mut hay_cmp_mask = _mm_movemask_epi8(hay_cmp) as u32;
+        while hay_cmp_mask != 0 {
+            let candidate = index + hay_cmp_mask.trailing_zeros() as usize;
+            if haystack.get_unchecked(candidate + 1..candidate + last_offset) == &needle[1..last_offset] {
+                return Some(candidate)
+            }
+            hay_cmp_mask &= hay_cmp_mask - 1;
+        }
+        index += SSE_LANE_WIDTH;
+    }
+
+    // If there are positions left over that don't fill a SIMD register, check them individually.
+    fallback_fastmem(needle, haystack, index)
+}
+
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn avx_fastmem(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+
+    let last_offset = needle.len() - 1;
+    let ptr = haystack.as_ptr() as usize;
+    let mut index: usize = 0;
+
+    // Read two 32 byte lanes at a time, one where the needle would start and one where it would end, and check where
+    // both the first and last bytes of the needle match.
+    let wide_first = _mm256_set1_epi8(needle[0] as i8);
+    let wide_last = _mm256_set1_epi8(needle[last_offset] as i8);
+
+    while index + last_offset + AVX_LANE_WIDTH <= haystack.len() {
+        let hay_first = _mm256_loadu_si256((ptr + index) as *const __m256i);
+        let hay_last = _mm256_loadu_si256((ptr + index + last_offset) as *const __m256i);
+        let hay_cmp = _mm256_and_si256(
+            _mm256_cmpeq_epi8(hay_first, wide_first),
+            _mm256_cmpeq_epi8(hay_last, wide_last),
+        );
+        let mut hay_cmp_mask = _mm256_movemask_epi8(hay_cmp) as u32;
+        while hay_cmp_mask != 0 {
+            let candidate = index + hay_cmp_mask.trailing_zeros() as usize;
+            if haystack.get_unchecked(candidate + 1..candidate + last_offset) == &needle[1..last_offset] {
+                return Some(candidate)
+            }
+            hay_cmp_mask &= hay_cmp_mask - 1;
+        }
+        index += AVX_LANE_WIDTH;
+    }
+
+    // If there are positions left over that don't fill a SIMD register, check them individually.
+    fallback_fastmem(needle, haystack, index)
+}
@@ -4,7 +4,7 @@ extern crate memchr;
 extern crate quickcheck;
 extern crate rand;
 
-use fastchr::{fastchr, Fastchr};
+use fastchr::{fastchr, fastmem, fastrmem, Fastchr};
 use memchr::{memchr, Memchr};
 use rand::{ChaChaRng, Rng};
 
@@ -117,6 +117,51 @@ fn memchr_dense_iter_count_equivalence() {
     assert_eq!(Memchr::new(NEEDLE, &dense_data).count(), Fastchr::new(NEEDLE, &dense_data).count(), "{}", identify_simd_feature_used())
 }
 
+fn naive_mem(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    (0..haystack.len() - needle.len() + 1).find(|&i| &haystack[i..i + needle.len()] == needle)
+}
+
+fn naive_rmem(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    (0..haystack.len() - needle.len() + 1).rev().find(|&i| &haystack[i..i + needle.len()] == needle)
+}
+
+#[test]
+fn mem_long_find_test() {
+    let mut data = generate_long_sample();
+    data[LONG_PREFIX_LEN - 1] = NEEDLE;
+    data[LONG_PREFIX_LEN + 1] = NEEDLE;
+    let needle = [14, NEEDLE, NEEDLE];
+    assert_eq!(Some(LONG_PREFIX_LEN - 2), fastmem(&needle, &data), "{}", identify_simd_feature_used());
+    assert_eq!(Some(LONG_PREFIX_LEN - 2), fastrmem(&needle, &data));
+}
+
+#[test]
+fn mem_dense_find_test() {
+    // Every lane matches the first and last bytes of the needle, but only the end of the data matches in full.
+    let mut data: Vec<u8> = iter::repeat(NEEDLE).take(ODD_PREFIX_LEN).collect();
+    data.extend_from_slice(&[14, NEEDLE]);
+    let needle = [NEEDLE, NEEDLE, 14, NEEDLE];
+    assert_eq!(Some(ODD_PREFIX_LEN - 2), fastmem(&needle, &data), "{}", identify_simd_feature_used());
+    assert_eq!(Some(ODD_PREFIX_LEN - 2), fastrmem(&needle, &data));
+}
+
+#[test]
+fn mem_edge_cases_test() {
+    assert_eq!(Some(0), fastmem(b"", b"spam"));
+    assert_eq!(Some(4), fastrmem(b"", b"spam"));
+    assert_eq!(None, fastmem(b"spam and eggs", b"spam"));
+    assert_eq!(None, fastrmem(b"spam and eggs", b"spam"));
+    assert_eq!(Some(0), fastmem(b"spam", b"spam"));
+    assert_eq!(Some(1), fastmem(b"\r\n", b"a\r\nb\r\n"));
+    assert_eq!(Some(4), fastrmem(b"\r\n", b"a\r\nb\r\n"));
+}
+
 fn identify_simd_feature_used() -> &'static str {
     if is_x86_feature_detected!("avx2") {
         "AVX"
@@ -137,4 +182,15 @@ quickcheck!{
         let m = Memchr::new(needle, &haystack);
         f.zip(m).all(|(f, m)| f == m)
     }
+
+    fn qc_mem_equivalence(needle: Vec<u8>, haystack: Vec<u8>) -> bool {
+        fastmem(&needle, &haystack) == naive_mem(&needle, &haystack)
+    }
+
+    fn qc_mem_small_alphabet_equivalence(needle: Vec<bool>, haystack: Vec<bool>) -> bool {
+        let needle: Vec<u8> = needle.into_iter().map(|b| b as u8).collect();
+        let haystack: Vec<u8> = haystack.into_iter().map(|b| b as u8).collect();
+        fastmem(&needle, &haystack) == naive_mem(&needle, &haystack)
+            && fastrmem(&needle, &haystack) == naive_rmem(&needle, &haystack)
+    }
 }
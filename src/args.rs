@@ -1,15 +1,51 @@
 use clap::App;
+use regex::bytes::Regex;
 
-use error::DedupError;
+use dedup::{BloomConfig, CaseFolding, CountPosition, DedupError, Deduper, Filter, Keep,
+            KeyFields, KeyRange, KeyRegex, Normalization, RangeUnit, SetOperation, Unmatched,
+            Whitespace, Window};
 
 use std::path::PathBuf;
+use std::str;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
 
 #[derive(Debug)]
 pub struct Args {
-    pub input: Option<PathBuf>,
+    pub inputs: Vec<Option<PathBuf>>,
+    pub exclusions: Vec<Option<PathBuf>>,
+    pub report: Option<ReportFormat>,
     pub output: Option<PathBuf>,
     pub mmap: bool,
-    pub delim: u8,
+    pub delim: Vec<u8>,
+    pub record_start: Option<Regex>,
+    pub add_terminator: bool,
+    pub count: bool,
+    pub occurrences: Option<CountPosition>,
+    pub filter: Filter,
+    pub keep: Keep,
+    pub set_operation: SetOperation,
+    pub key_fields: Option<KeyFields>,
+    pub key_range: Option<KeyRange>,
+    pub key_regex: Option<KeyRegex>,
+    pub unmatched: Unmatched,
+    pub whitespace: Whitespace,
+    pub case_folding: Option<CaseFolding>,
+    pub normalization: Option<Normalization>,
+    pub hash_only: bool,
+    pub bloom_filter: Option<BloomConfig>,
+    pub max_entries: Option<usize>,
+    pub verbose: bool,
+    pub max_memory: Option<usize>,
+    pub threads: usize,
+    pub spill_dir: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub window: Option<Window>,
 }
 
 impl Args {
@@ -17,77 +53,386 @@ impl Args {
         let yml = load_yaml!("../cli.yml");
         let m = App::from_yaml(yml).get_matches();
 
-        let input = m.value_of("INPUT")
-            .and_then(replace_with_stdout)
-            .map(PathBuf::from);
+        let inputs = m.values_of("INPUT").map_or_else(Vec::new, |values| {
+            values
+                .map(|input| replace_with_stdout(input).map(PathBuf::from))
+                .collect()
+        });
+        let exclusions = m.values_of("EXCLUDE_FROM").map_or_else(Vec::new, |values| {
+            values
+                .map(|input| replace_with_stdout(input).map(PathBuf::from))
+                .collect()
+        });
+        let report = if m.is_present("REPORT") {
+            match m.value_of("REPORT_FORMAT") {
+                Some("json") => Some(ReportFormat::Json),
+                _ => Some(ReportFormat::Text),
+            }
+        } else {
+            None
+        };
         let output = m.value_of("OUTPUT").map(PathBuf::from);
         let mmap = !m.is_present("NO_MMAP");
         let delim = m.value_of("DELIMITER")
-            .map_or(Ok(b'\n'), parse_to_byte_literal)?;
+            .map_or(Ok(vec![b'\n']), parse_delimiter)?;
+        let record_start = match m.value_of("RECORD_START") {
+            Some(s) => Some(parse_regex(s)?),
+            None => None,
+        };
+        let add_terminator = m.is_present("ADD_TERMINATOR");
+        let count = m.is_present("COUNT");
+        let occurrences = if m.is_present("OCCURRENCES") {
+            match m.value_of("OCCURRENCE_POSITION") {
+                Some("suffix") => Some(CountPosition::Suffix),
+                _ => Some(CountPosition::Prefix),
+            }
+        } else {
+            None
+        };
+        let filter = if m.is_present("REPEATED") {
+            Filter::Repeated
+        } else if m.is_present("UNREPEATED") {
+            Filter::Unrepeated
+        } else {
+            Filter::All
+        };
+        let keep = match m.value_of("KEEP") {
+            Some("last") => Keep::Last,
+            _ => Keep::First,
+        };
+        let set_operation = if m.is_present("INTERSECT") {
+            SetOperation::Intersection
+        } else if m.is_present("SYMMETRIC_DIFFERENCE") {
+            SetOperation::SymmetricDifference
+        } else {
+            SetOperation::Union
+        };
+        let field_sep = m.value_of("FIELD_SEP")
+            .map_or(Ok(b'\t'), parse_to_byte_literal)?;
+        let key_fields = match m.value_of("KEY_FIELD") {
+            Some(s) => Some(parse_field_list(s, field_sep)?),
+            None => None,
+        };
+        let skip = m.value_of("SKIP").map_or(Ok(None), parse_count)?;
+        let check = m.value_of("CHECK").map_or(Ok(None), parse_count)?;
+        let key_range = if skip.is_some() || check.is_some() {
+            Some(KeyRange {
+                skip: skip.unwrap_or(0),
+                length: check,
+                unit: if m.is_present("CHARS") {
+                    RangeUnit::Chars
+                } else {
+                    RangeUnit::Bytes
+                },
+            })
+        } else {
+            None
+        };
+        let key_regex = match m.value_of("KEY_REGEX") {
+            Some(s) => Some(KeyRegex::new(parse_regex(s)?, m.value_of("KEY_GROUP"))?),
+            None => None,
+        };
+        let unmatched = match m.value_of("UNMATCHED") {
+            Some("drop") => Unmatched::Drop,
+            _ => Unmatched::Pass,
+        };
+        let whitespace = Whitespace {
+            trim: m.is_present("TRIM"),
+            collapse: m.is_present("COLLAPSE_WHITESPACE"),
+            ignore_trailing_cr: m.is_present("IGNORE_TRAILING_CR"),
+        };
+        let case_folding = if m.is_present("IGNORE_CASE") {
+            match m.value_of("CASE_FOLDING") {
+                Some("ascii") => Some(CaseFolding::Ascii),
+                _ => Some(CaseFolding::Unicode),
+            }
+        } else {
+            None
+        };
+        let normalization = match m.value_of("NORMALIZE") {
+            Some("nfc") => Some(Normalization::Nfc),
+            Some("nfkc") => Some(Normalization::Nfkc),
+            _ => None,
+        };
+        let hash_only = m.is_present("HASH_ONLY");
+        let bloom_filter = if m.is_present("APPROXIMATE") {
+            let expected_keys = m.value_of("EXPECTED_ENTRIES").map_or(Ok(None), parse_count)?;
+            let false_positive_rate = m.value_of("FALSE_POSITIVE_RATE")
+                .map_or(Ok(0.0001), parse_rate)?;
+            Some(BloomConfig {
+                expected_keys: expected_keys.unwrap_or(10_000_000) as u64,
+                false_positive_rate,
+            })
+        } else {
+            None
+        };
+        let max_entries = m.value_of("MAX_ENTRIES").map_or(Ok(None), parse_count)?;
+        if max_entries == Some(0) {
+            return Err(DedupError::ArgumentParseError(
+                "--max-entries must be at least 1.".to_string(),
+            ));
+        }
+        let verbose = m.is_present("VERBOSE");
+        let max_memory = m.value_of("MAX_MEMORY").map_or(Ok(None), parse_size)?;
+        let threads = m.value_of("THREADS").map_or(Ok(None), parse_count)?.unwrap_or(1);
+        let spill_dir = m.value_of("SPILL_DIR").map(PathBuf::from);
+        let state = m.value_of("STATE").map(PathBuf::from);
+        let window = m.value_of("WINDOW").map_or(Ok(None), parse_window)?;
+        if window.is_some() && keep == Keep::Last {
+            return Err(DedupError::ArgumentParseError(
+                "--keep last cannot be used with --window, since entries are written as soon as \
+                 they are read."
+                    .to_string(),
+            ));
+        }
         
         Ok(Args {
-            input,
+            inputs,
+            exclusions,
+            report,
             output,
             mmap,
             delim,
+            record_start,
+            add_terminator,
+            count,
+            occurrences,
+            filter,
+            keep,
+            set_operation,
+            key_fields,
+            key_range,
+            key_regex,
+            unmatched,
+            whitespace,
+            case_folding,
+            normalization,
+            hash_only,
+            bloom_filter,
+            max_entries,
+            verbose,
+            max_memory,
+            threads,
+            spill_dir,
+            state,
+            window,
         })
     }
-}
 
-pub struct Options {
-    pub delim: u8,
+    pub fn deduper(&self) -> Deduper {
+        let mut deduper = Deduper::new();
+        deduper
+            .delimiter(&self.delim)
+            .record_start(self.record_start.clone())
+            .add_terminator(self.add_terminator)
+            .count_only(self.count)
+            .occurrences(self.occurrences)
+            .filter(self.filter)
+            .keep(self.keep)
+            .set_operation(self.set_operation)
+            .key_fields(self.key_fields.clone())
+            .key_range(self.key_range)
+            .key_regex(self.key_regex.clone())
+            .unmatched(self.unmatched)
+            .whitespace(self.whitespace)
+            .case_folding(self.case_folding)
+            .normalization(self.normalization)
+            .hash_only(self.hash_only)
+            .bloom_filter(self.bloom_filter)
+            .max_entries(self.max_entries)
+            .window(self.window)
+            .max_memory(self.max_memory)
+            .threads(self.threads)
+            .spill_dir(self.spill_dir.clone());
+        deduper
+    }
 }
 
-impl Default for Options {
-    fn default() -> Self {
-        Options {
-            delim: b'\n',
-        }
+fn parse_to_byte_literal(input: &str) -> Result<u8, DedupError> {
+    match &parse_escaped(input)?[..] {
+        &[b] => Ok(b),
+        _ => Err(DedupError::ArgumentParseError(format!(
+            "Invalid separator specified, only single byte characters are permitted. Found: {}",
+            input
+        ))),
     }
 }
 
-impl From<Args> for Options {
-    fn from(src: Args) -> Self {
-        Options {
-            delim: src.delim,
-        }
+fn parse_delimiter(input: &str) -> Result<Vec<u8>, DedupError> {
+    let delim = parse_escaped(input)?;
+    if delim.is_empty() {
+        return Err(DedupError::ArgumentParseError(
+            "Invalid delimiter specified, the delimiter must not be empty.".to_string(),
+        ));
     }
+    Ok(delim)
 }
 
-impl<'a> From<&'a Args> for Options {
-    fn from(src: &'a Args) -> Self {
-        Options {
-            delim: src.delim,
+/// Parses a string in which a backslash starts one of the escape sequences `\n`, `\r`, `\t`, `\e`,
+/// `\a`, `\b`, `\f`, `\v`, `\\`, `\'`, `\"`, `\xNN` (hexadecimal), `\NNN` (octal, such as `\0`) or
+/// `\dNNN` (decimal).
+fn parse_escaped(input: &str) -> Result<Vec<u8>, DedupError> {
+    let bytes = input.as_bytes();
+    let mut parsed = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            parsed.push(bytes[i]);
+            i += 1;
+            continue;
         }
+        let (b, len) = parse_escape(&bytes[i + 1..]).ok_or_else(|| {
+            DedupError::ArgumentParseError(format!(
+                "Invalid escape sequence '{}' at position {}. Found: {}",
+                input[i..].chars().take(5).collect::<String>(),
+                input[..i].chars().count() + 1,
+                input
+            ))
+        })?;
+        parsed.push(b);
+        i += 1 + len;
     }
+    Ok(parsed)
 }
 
-fn parse_to_byte_literal(input: &str) -> Result<u8, DedupError> {
-    if input.len() == 1 {
-        return Ok(input.as_bytes()[0]);
+/// Parses the escape sequence that follows a backslash at the start of `rest`, returning the byte
+/// it stands for and how many bytes of `rest` it spans.
+fn parse_escape(rest: &[u8]) -> Option<(u8, usize)> {
+    let b = match *rest.first()? {
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'e' => 0x1b,
+        b'a' => 0x07,
+        b'b' => 0x08,
+        b'f' => 0x0c,
+        b'v' => 0x0b,
+        b'\\' => b'\\',
+        b'\'' => b'\'',
+        b'"' => b'"',
+        b'x' => return parse_digits(&rest[1..], 16, 2, 2).map(|(b, len)| (b, len + 1)),
+        b'd' => return parse_digits(&rest[1..], 10, 1, 3).map(|(b, len)| (b, len + 1)),
+        b'0'..=b'7' => return parse_digits(rest, 8, 1, 3),
+        _ => return None,
+    };
+    Some((b, 1))
+}
+
+/// Parses between `min` and `max` leading digits of `digits` in the given radix as a byte,
+/// returning it and the number of digits used.
+fn parse_digits(digits: &[u8], radix: u32, min: usize, max: usize) -> Option<(u8, usize)> {
+    let len = digits
+        .iter()
+        .take(max)
+        .take_while(|&&b| (b as char).is_digit(radix))
+        .count();
+    if len < min {
+        return None;
     }
-    if input.len() > 2 {
-        return Err(DedupError::ArgumentParseError(format!(
-            "Invalid delimiter specified, only single byte characters are permitted. Found: {}",
+    let digits = str::from_utf8(&digits[..len]).ok()?;
+    u8::from_str_radix(digits, radix).ok().map(|b| (b, len))
+}
+
+fn parse_field_list(input: &str, separator: u8) -> Result<KeyFields, DedupError> {
+    let invalid = || {
+        DedupError::ArgumentParseError(format!(
+            "Invalid key fields specified, expected numbers such as '2' or '1,3-4'. Found: {}",
             input
-        )));
+        ))
+    };
+
+    let mut ranges = Vec::new();
+    for part in input.split(',') {
+        let mut bounds = part.splitn(2, '-');
+        let start: usize = bounds.next().unwrap().parse().map_err(|_| invalid())?;
+        let end: usize = match bounds.next() {
+            Some(end) => end.parse().map_err(|_| invalid())?,
+            None => start,
+        };
+        ranges.push((start, end));
     }
 
-    let bytes = input.as_bytes();
-    match (bytes[0], bytes[1]) {
-        (b'\\', b'n') => Ok(b'\n'),
-        (b'\\', b't') => Ok(b'\t'),
-        (b'\\', b'0') => Ok(b'\0'),
-        (b'\\', b'\\') => Ok(b'\\'),
-        (b'\\', b'\'') => Ok(b'\''),
-        (b'\\', b'"') => Ok(b'\"'),
-        (_, _) => Err(DedupError::ArgumentParseError(format!(
-            "Invalid delimiter specified, only single byte characters are permitted. Found: {}",
+    KeyFields::from_ranges(ranges, separator).ok_or_else(invalid)
+}
+
+fn parse_count(input: &str) -> Result<Option<usize>, DedupError> {
+    input.parse().map(Some).map_err(|_| {
+        DedupError::ArgumentParseError(format!(
+            "Invalid count specified, expected a non-negative integer. Found: {}",
+            input
+        ))
+    })
+}
+
+fn parse_rate(input: &str) -> Result<f64, DedupError> {
+    match input.parse() {
+        Ok(rate) if rate > 0.0 && rate < 1.0 => Ok(rate),
+        _ => Err(DedupError::ArgumentParseError(format!(
+            "Invalid rate specified, expected a number between 0 and 1 such as 0.001. Found: {}",
             input
         ))),
     }
 }
 
+/// Parses a number of entries, or a time followed by an `s`, `m`, `h` or `d` suffix.
+fn parse_window(input: &str) -> Result<Option<Window>, DedupError> {
+    let invalid = || {
+        DedupError::ArgumentParseError(format!(
+            "Invalid window specified, expected a number of entries such as '1000' or a time such \
+             as '30s', '5m', '2h' or '1d'. Found: {}",
+            input
+        ))
+    };
+
+    let digits = input.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let n: u64 = match digits.parse() {
+        Ok(n) if n > 0 => n,
+        _ => return Err(invalid()),
+    };
+    let seconds = match &input[digits.len()..] {
+        "" => return Ok(Some(Window::Entries(n))),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let seconds = n.checked_mul(seconds).ok_or_else(invalid)?;
+    Ok(Some(Window::Time(Duration::from_secs(seconds))))
+}
+
+/// Parses a number of bytes, optionally followed by a `K`, `M`, `G` or `T` suffix for powers of
+/// 1024.
+fn parse_size(input: &str) -> Result<Option<usize>, DedupError> {
+    let invalid = || {
+        DedupError::ArgumentParseError(format!(
+            "Invalid size specified, expected a number of bytes such as '512M' or '2G'. Found: {}",
+            input
+        ))
+    };
+
+    let digits = input.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let shift = match &input[digits.len()..] {
+        "" | "B" | "b" => 0,
+        "K" | "k" => 10,
+        "M" | "m" => 20,
+        "G" | "g" => 30,
+        "T" | "t" => 40,
+        _ => return Err(invalid()),
+    };
+    let size: usize = digits.parse().map_err(|_| invalid())?;
+    size.checked_mul(1 << shift)
+        .filter(|&size| size > 0)
+        .map(Some)
+        .ok_or_else(invalid)
+}
+
+fn parse_regex(input: &str) -> Result<Regex, DedupError> {
+    Regex::new(input).map_err(|e| {
+        DedupError::ArgumentParseError(format!("Invalid regular expression specified. {}", e))
+    })
+}
+
 fn replace_with_stdout(input: &str) -> Option<&str> {
     if input == "-" {
         None
@@ -107,6 +452,33 @@ mod tests {
         assert_eq!(m.value_of("INPUT"), Some("inputfile"));
     }
 
+    #[test]
+    fn multiple_input_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "a.txt", "-", "c.txt", "-l"]);
+
+        let inputs: Vec<_> = m.values_of("INPUT").unwrap().map(replace_with_stdout).collect();
+        assert_eq!(inputs, vec![Some("a.txt"), None, Some("c.txt")]);
+        assert!(m.is_present("COUNT"));
+    }
+
+    #[test]
+    fn exclude_from_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec![
+            "dedup",
+            "--exclude-from",
+            "seen.txt",
+            "new.txt",
+            "--exclude-from",
+            "old.txt",
+        ]);
+
+        let exclusions: Vec<_> = m.values_of("EXCLUDE_FROM").unwrap().collect();
+        assert_eq!(exclusions, vec!["seen.txt", "old.txt"]);
+        assert_eq!(m.value_of("INPUT"), Some("new.txt"));
+    }
+
     #[test]
     fn no_mmap_test() {
         let yml = load_yaml!("../cli.yml");
@@ -145,6 +517,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn multi_byte_delim_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "-z", "\\r\\n", "inputfile"]);
+
+        assert_eq!(
+            parse_delimiter(m.value_of("DELIMITER").unwrap()).unwrap(),
+            b"\r\n"
+        );
+        assert_eq!(parse_delimiter("---").unwrap(), b"---");
+        assert!(parse_delimiter("").is_err());
+        assert!(parse_delimiter("\\").is_err());
+        assert!(parse_to_byte_literal("\\t\\t").is_err());
+    }
+
+    #[test]
+    fn escaped_delim_test() {
+        assert_eq!(parse_delimiter("\\x1e").unwrap(), b"\x1e");
+        assert_eq!(parse_delimiter("\\x1E\\e").unwrap(), b"\x1e\x1b");
+        assert_eq!(parse_delimiter("\\036\\0").unwrap(), b"\x1e\0");
+        assert_eq!(parse_delimiter("\\d30\\d255").unwrap(), b"\x1e\xff");
+        assert_eq!(parse_delimiter("--\\x2d").unwrap(), b"---");
+        assert_eq!(parse_to_byte_literal("\\v").unwrap(), 0x0b);
+
+        for invalid in &["\\x1", "\\xzz", "\\400", "\\d256", "\\d", "\\q", "a\\"] {
+            assert!(parse_delimiter(invalid).is_err(), "{}", invalid);
+        }
+        match parse_delimiter("ab\\xzz") {
+            Err(DedupError::ArgumentParseError(msg)) => {
+                assert!(msg.contains("'\\xzz' at position 3"), "{}", msg)
+            }
+            _ => panic!("expected an argument parse error"),
+        }
+    }
+
     #[test]
     fn unspecified_delim_test() {
         let yml = load_yaml!("../cli.yml");
@@ -152,4 +559,153 @@ mod tests {
 
         assert!(!m.is_present("DELIMITER"));
     }
+
+    #[test]
+    fn count_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "-l", "inputfile"]);
+        assert!(m.is_present("COUNT"));
+
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "--count-lines", "inputfile"]);
+        assert!(m.is_present("COUNT"));
+    }
+
+    #[test]
+    fn key_field_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "-f", "1,3-4", "inputfile"]);
+
+        assert_eq!(
+            parse_field_list(m.value_of("KEY_FIELD").unwrap(), b'\t').unwrap(),
+            KeyFields::new(vec![1, 3, 4], b'\t').unwrap()
+        );
+        assert!(parse_field_list("0", b'\t').is_err());
+        assert!(parse_field_list("3-1", b'\t').is_err());
+        assert!(parse_field_list("spam", b'\t').is_err());
+        assert!(parse_field_list("1-4000000000", b'\t').is_ok());
+        let max = usize::max_value();
+        assert!(parse_field_list(&format!("1-{}", max), b'\t').is_ok());
+        assert!(parse_field_list(&format!("{}-{}", max, max), b'\t').is_ok());
+        assert!(parse_field_list(&format!("1-{}0", max), b'\t').is_err());
+    }
+
+    #[test]
+    fn hash_only_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "--hash-only", "-v"]);
+
+        assert!(m.is_present("HASH_ONLY"));
+        assert!(m.is_present("VERBOSE"));
+    }
+
+    #[test]
+    fn approximate_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec![
+            "dedup",
+            "--approximate",
+            "--expected-entries",
+            "5000",
+            "--false-positive-rate",
+            "0.01",
+        ]);
+
+        assert!(m.is_present("APPROXIMATE"));
+        assert_eq!(parse_count(m.value_of("EXPECTED_ENTRIES").unwrap()).unwrap(), Some(5000));
+        assert_eq!(parse_rate(m.value_of("FALSE_POSITIVE_RATE").unwrap()).unwrap(), 0.01);
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("1").is_err());
+        assert!(parse_rate("NaN").is_err());
+    }
+
+    #[test]
+    fn state_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec![
+            "dedup",
+            "--state",
+            "seen.state",
+            "new.log",
+        ]);
+
+        assert_eq!(m.value_of("STATE"), Some("seen.state"));
+        assert_eq!(m.value_of("INPUT"), Some("new.log"));
+
+        // Saving the keys of entries that weren't written would suppress them in later runs.
+        for flag in &["--count", "--repeated", "--unrepeated", "--intersect"] {
+            let m = App::from_yaml(yml)
+                .get_matches_from_safe(vec!["dedup", "--state", "seen.state", flag, "new.log"]);
+            assert!(m.is_err());
+        }
+    }
+
+    #[test]
+    fn window_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "--window", "5m"]);
+
+        assert_eq!(
+            parse_window(m.value_of("WINDOW").unwrap()).unwrap(),
+            Some(Window::Time(Duration::from_secs(300)))
+        );
+        assert_eq!(parse_window("1000").unwrap(), Some(Window::Entries(1000)));
+        assert_eq!(
+            parse_window("1d").unwrap(),
+            Some(Window::Time(Duration::from_secs(86_400)))
+        );
+        assert!(parse_window("0").is_err());
+        assert!(parse_window("s").is_err());
+        assert!(parse_window("10w").is_err());
+    }
+
+    #[test]
+    fn max_memory_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec![
+            "dedup",
+            "--max-memory",
+            "512M",
+            "--spill-dir",
+            "/var/tmp",
+            "inputfile",
+        ]);
+
+        assert_eq!(parse_size(m.value_of("MAX_MEMORY").unwrap()).unwrap(), Some(512 << 20));
+        assert_eq!(m.value_of("SPILL_DIR"), Some("/var/tmp"));
+        assert_eq!(parse_size("4096").unwrap(), Some(4096));
+        assert_eq!(parse_size("2k").unwrap(), Some(2048));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("12Q").is_err());
+        assert!(parse_size("-1K").is_err());
+
+        let m = App::from_yaml(yml).get_matches_from_safe(vec![
+            "dedup",
+            "--max-memory",
+            "512M",
+            "--record-start",
+            "^",
+            "inputfile",
+        ]);
+        assert!(m.is_err());
+    }
+
+    #[test]
+    fn max_entries_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "--max-entries", "1000"]);
+        assert_eq!(parse_count(m.value_of("MAX_ENTRIES").unwrap()).unwrap(), Some(1000));
+
+        let m = App::from_yaml(yml)
+            .get_matches_from_safe(vec!["dedup", "--max-entries", "1000", "--approximate"]);
+        assert!(m.is_err());
+    }
+
+    #[test]
+    fn threads_test() {
+        let yml = load_yaml!("../cli.yml");
+        let m = App::from_yaml(yml).get_matches_from(vec!["dedup", "--threads", "0", "inputfile"]);
+        assert_eq!(parse_count(m.value_of("THREADS").unwrap()).unwrap(), Some(0));
+        assert!(parse_count("-2").is_err());
+    }
 }
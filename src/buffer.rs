@@ -1,51 +1,363 @@
-use fastchr::fastchr;
-
-use args::Options;
+use key::Unmatched;
+use options::{Keep, Options};
 use error::DedupError;
-use set::Set;
+use parallel;
+use records::{Record, Splitter};
+use report::{Duplicate, Occurrence, Report};
+use set::{BloomFilter, FingerprintSet, KeySet, LruSet, Map, Set};
+use stats::Stats;
 
+use std::borrow::Cow;
 use std::io;
+use std::mem;
 use std::default::Default;
 
+/// Deduplicates the entries of an in-memory buffer, such as a memory mapped file, or of several
+/// buffers in turn.
+///
+/// Entries are borrowed from the buffer rather than copied, so this is considerably faster than
+/// [`UnsortedStreamDeduper`](struct.UnsortedStreamDeduper.html) when the whole input is available.
 pub struct BufferDeduper<'a, W: io::Write + 'a> {
-    buffer: &'a [u8],
+    buffers: Vec<&'a [u8]>,
     opts: Options,
     out: W,
-    dup_store: Set<&'a [u8]>,
+    dup_store: Box<dyn KeySet<Cow<'a, [u8]>> + 'a>,
+    excluded: Set<Cow<'a, [u8]>>,
+    /// The keys seen by earlier runs, which replaces `dup_store` while `run_with_state` runs.
+    state: Option<FingerprintSet>,
 }
 
 impl<'a, W: io::Write + 'a> BufferDeduper<'a, W> {
-    pub fn new<R: AsRef<[u8]>>(buffer: &'a R, output: W, opts: Options) -> Self {
+    /// Creates a new `BufferDeduper` that reads entries from `buffer` and writes to `output`.
+    pub fn new<R: AsRef<[u8]> + ?Sized>(buffer: &'a R, output: W, opts: Options) -> Self {
+        BufferDeduper::from_buffers(Some(buffer), output, opts)
+    }
+
+    /// Creates a new `BufferDeduper` that reads entries from each of `buffers` in turn, as though
+    /// they were a single input, and writes to `output`.
+    ///
+    /// An entry never spans two buffers. If a buffer other than the last does not end with a
+    /// delimiter, one is written after its final entry so that it stays apart from the next.
+    pub fn from_buffers<I, R>(buffers: I, output: W, opts: Options) -> Self
+    where
+        I: IntoIterator<Item = &'a R>,
+        R: AsRef<[u8]> + ?Sized + 'a,
+    {
+        let dup_store: Box<dyn KeySet<Cow<'a, [u8]>> + 'a> = match opts.bloom_filter {
+            Some(config) => Box::new(BloomFilter::new(config)),
+            None => match opts.max_entries {
+                Some(entries) => Box::new(LruSet::new(entries)),
+                None => Box::new(Set::default()),
+            },
+        };
         BufferDeduper {
-            buffer: buffer.as_ref(),
+            buffers: buffers.into_iter().map(AsRef::as_ref).collect(),
             out: output,
-            dup_store: Set::with_capacity_and_hasher(
-                (buffer.as_ref().len() / 256).next_power_of_two(),
-                Default::default(),
-            ),
+            dup_store,
+            excluded: Set::default(),
+            state: None,
             opts,
         }
     }
 
-    pub fn run(mut self) -> Result<u64, DedupError> {
-        let delim = self.opts.delim;
-        let mut count: u64 = 0;
-        while let Some(u) = fastchr(delim, self.buffer) {
-            let (mut ele, rest) = self.buffer.split_at(u + 1);
-            if self.dup_store.insert(ele) {
-                self.out.write_all(ele)?;
+    /// Excludes every entry of `buffer` from the output, as though each had already been written.
+    /// Entries are compared by the same key as the input, and are not counted in the statistics.
+    pub fn exclude<R: AsRef<[u8]> + ?Sized>(&mut self, buffer: &'a R) {
+        let splitter = Splitter::new(&self.opts);
+        for record in splitter.split(buffer.as_ref()) {
+            if let Some(key) = self.opts.key(record.body) {
+                self.excluded.insert(key);
+            }
+        }
+    }
+
+    /// Excludes entries whose keys have already been extracted.
+    pub(crate) fn exclude_keys<I: IntoIterator<Item = Vec<u8>>>(&mut self, keys: I) {
+        self.excluded.extend(keys.into_iter().map(Cow::Owned));
+    }
+
+    /// Writes each unique entry to the output, returning statistics about the run.
+    pub fn run(mut self) -> Result<Stats, DedupError> {
+        self.run_all()
+    }
+
+    /// Like `run`, but entries whose keys are in `state`, such as those seen by earlier runs, are
+    /// never written, as though they had already been seen. The key of every entry read that is
+    /// not excluded is added to `state`, whether or not the entry is written.
+    pub fn run_with_state(mut self, state: &mut FingerprintSet) -> Result<Stats, DedupError> {
+        self.state = Some(mem::replace(state, Default::default()));
+        let stats = self.run_all();
+        *state = self.state.take().unwrap_or_default();
+        stats
+    }
+
+    fn run_all(&mut self) -> Result<Stats, DedupError> {
+        if self.opts.needs_counts() {
+            return self.run_counted();
+        }
+        if self.opts.keep == Keep::Last {
+            return self.run_keep_last();
+        }
+        let exact = self.opts.bloom_filter.is_none() && self.opts.max_entries.is_none();
+        if self.opts.threads != 1 && exact && self.state.is_none() {
+            let threads = self.opts.threads;
+            let buffers = self.buffers.clone();
+            if let Some(stats) =
+                parallel::run(&buffers, &mut self.out, &self.opts, &self.excluded, threads)?
+            {
+                return Ok(stats);
+            }
+        }
+
+        self.dup_store.reserve(capacity_hint(&self.buffers));
+        let splitter = Splitter::new(&self.opts);
+        let buffers = self.buffers.clone();
+        let dup_store: &mut dyn KeySet<Cow<'a, [u8]>> = match self.state {
+            Some(ref mut state) => state,
+            None => &mut *self.dup_store,
+        };
+        let mut stats = Stats::default();
+        for record in splitter.split_all(&buffers) {
+            stats.total += 1;
+            let unique = match self.opts.key(record.body) {
+                Some(ref key) if self.excluded.contains(&key[..]) => {
+                    stats.excluded += 1;
+                    continue;
+                }
+                Some(key) => dup_store.insert(key),
+                None if self.opts.unmatched == Unmatched::Pass => true,
+                None => {
+                    stats.dropped += 1;
+                    continue;
+                }
+            };
+            if unique {
+                stats.unique += 1;
+                if !self.opts.count_only {
+                    record.write_to(&mut self.out, &self.opts)?;
+                }
+            }
+        }
+
+        stats.evictions = dup_store.evictions();
+        Ok(stats)
+    }
+
+    /// Finds where each duplicated entry occurs instead of writing the unique entries.
+    ///
+    /// Excluded entries and entries without a key are left out of the report.
+    pub fn report(self) -> Report<'a> {
+        let splitter = Splitter::new(&self.opts);
+        let mut groups: Map<Cow<'a, [u8]>, usize> =
+            Map::with_capacity_and_hasher(capacity_hint(&self.buffers), Default::default());
+        let mut entries: Vec<Duplicate<'a>> = Vec::new();
+        for (input, buffer) in self.buffers.iter().enumerate() {
+            let mut offset = 0;
+            for (i, record) in splitter.split(buffer).enumerate() {
+                let occurrence = Occurrence {
+                    input,
+                    record: i as u64 + 1,
+                    offset,
+                };
+                offset += record.raw.len();
+                let key = match self.opts.key(record.body) {
+                    Some(ref key) if self.excluded.contains(&key[..]) => continue,
+                    Some(key) => key,
+                    None => continue,
+                };
+                let index = *groups.entry(key).or_insert_with(|| {
+                    entries.push(Duplicate {
+                        entry: record.body,
+                        occurrences: Vec::new(),
+                    });
+                    entries.len() - 1
+                });
+                entries[index].occurrences.push(occurrence);
+            }
+        }
+
+        entries.retain(|entry| entry.occurrences.len() > 1);
+        Report {
+            duplicates: entries,
+        }
+    }
+
+    /// Scans the buffer backwards so that the first occurrence seen of each entry is its last.
+    /// Only the kept entries are collected, so that they can be written back in input order.
+    fn run_keep_last(&mut self) -> Result<Stats, DedupError> {
+        self.dup_store.reserve(capacity_hint(&self.buffers));
+        let splitter = Splitter::new(&self.opts);
+        let buffers = self.buffers.clone();
+        let dup_store: &mut dyn KeySet<Cow<'a, [u8]>> = match self.state {
+            Some(ref mut state) => state,
+            None => &mut *self.dup_store,
+        };
+        let mut stats = Stats::default();
+        let mut kept = Vec::new();
+        for record in splitter.split_all(&buffers).rev() {
+            stats.total += 1;
+            let unique = match self.opts.key(record.body) {
+                Some(ref key) if self.excluded.contains(&key[..]) => {
+                    stats.excluded += 1;
+                    continue;
+                }
+                Some(key) => dup_store.insert(key),
+                None if self.opts.unmatched == Unmatched::Pass => true,
+                None => {
+                    stats.dropped += 1;
+                    continue;
+                }
+            };
+            if unique {
+                kept.push(record);
+            }
+        }
+        stats.unique = kept.len() as u64;
+        stats.evictions = dup_store.evictions();
+
+        if !self.opts.count_only {
+            for record in kept.iter().rev() {
+                record.write_to(&mut self.out, &self.opts)?;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Counts every entry, and the inputs it occurs in, in a first pass over the buffers, then
+    /// writes each unique entry that passes the filter and set operation in a second pass.
+    ///
+    /// Entries whose keys are in the state are left out of the tallies, so that they are never
+    /// written. The keys of the rest are added to the state once both passes are complete.
+    fn run_counted(&mut self) -> Result<Stats, DedupError> {
+        let mut tallies: Map<Cow<'a, [u8]>, Tally> =
+            Map::with_capacity_and_hasher(capacity_hint(&self.buffers), Default::default());
+        let splitter = Splitter::new(&self.opts);
+        let buffers = self.buffers.clone();
+        let mut stats = Stats::default();
+        let mut passed = 0;
+        for (input, buffer) in buffers.iter().enumerate() {
+            for record in splitter.split(buffer) {
+                stats.total += 1;
+                match self.opts.key(record.body) {
+                    Some(ref key) if self.excluded.contains(&key[..]) => stats.excluded += 1,
+                    Some(ref key) if self.seen_before(key) => {}
+                    Some(key) => tallies.entry(key).or_default().add(input),
+                    None if self.opts.unmatched == Unmatched::Pass => passed += 1,
+                    None => stats.dropped += 1,
+                }
+            }
+        }
+        stats.unique = tallies.len() as u64 + passed;
+        if let Some(ref mut state) = self.state {
+            for key in tallies.keys() {
+                state.insert(key);
+            }
+        }
+
+        if self.opts.count_only {
+            return Ok(stats);
+        }
+
+        let records = splitter.split_all(&buffers);
+        match self.opts.keep {
+            Keep::First => {
+                for record in records {
+                    if let Some(tally) = self.take_tally(&mut tallies, &record) {
+                        self.write_counted(&record, tally)?;
+                    }
+                }
+            }
+            Keep::Last => {
+                let mut kept = Vec::new();
+                for record in records.rev() {
+                    if let Some(tally) = self.take_tally(&mut tallies, &record) {
+                        kept.push((record, tally));
+                    }
+                }
+                for &(ref record, tally) in kept.iter().rev() {
+                    self.write_counted(record, tally)?;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Whether `key` is in the state of earlier runs, if there is one.
+    fn seen_before(&self, key: &[u8]) -> bool {
+        self.state.as_ref().map_or(false, |state| state.contains(key))
+    }
+
+    /// Removes and returns the tally of `record`'s key, so that only one occurrence is written.
+    /// Entries without a key that are passed through occur once, in a single input.
+    fn take_tally(
+        &self,
+        tallies: &mut Map<Cow<'a, [u8]>, Tally>,
+        record: &Record,
+    ) -> Option<Tally> {
+        match self.opts.key(record.body) {
+            Some(key) => tallies.remove(&key[..]),
+            None if self.opts.unmatched == Unmatched::Pass => {
+                let mut tally = Tally::default();
+                tally.add(0);
+                Some(tally)
             }
-            self.buffer = rest;
-            count += 1;
+            None => None,
         }
+    }
 
-        Ok(count)
+    fn write_counted(&mut self, record: &Record, tally: Tally) -> io::Result<()> {
+        let written = self.opts.filter.matches(tally.count)
+            && self.opts
+                .set_operation
+                .matches(tally.inputs, self.buffers.len());
+        if written {
+            record.write_counted_to(&mut self.out, tally.count, &self.opts)
+        } else {
+            Ok(())
+        }
     }
 }
 
+/// How many times a key occurs, and in how many of the inputs.
+///
+/// Only the last input is remembered, so `inputs` is only right if occurrences are added in input
+/// order: every occurrence in one input before any in the next. `SetOperation::Intersection` and
+/// `SetOperation::SymmetricDifference` rely on this, so the inputs must be scanned one after the
+/// other rather than interleaved.
+#[derive(Debug, Clone, Copy, Default)]
+struct Tally {
+    count: u64,
+    inputs: usize,
+    /// The last input the key occurred in.
+    last_input: usize,
+}
+
+impl Tally {
+    /// Adds an occurrence in `input`, which must be no earlier than any input already added.
+    fn add(&mut self, input: usize) {
+        debug_assert!(self.count == 0 || input >= self.last_input);
+        if self.count == 0 || self.last_input != input {
+            self.inputs += 1;
+            self.last_input = input;
+        }
+        self.count += 1;
+    }
+}
+
+fn capacity_hint(buffers: &[&[u8]]) -> usize {
+    let len: usize = buffers.iter().map(|buffer| buffer.len()).sum();
+    (len / 256).next_power_of_two()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use options::{CountPosition, Filter, Keep, SetOperation};
+    use key::{CaseFolding, KeyFields, KeyRange, KeyRegex, Normalization, RangeUnit, Unmatched};
+    use regex::bytes::Regex;
+    use set::BloomConfig;
     use std::str;
     static BREAKFAST: &str = "\
 spam
@@ -74,4 +386,450 @@ ham eggs
         }
         assert_eq!(BREAKFAST_DEDUP, str::from_utf8(&output).unwrap());
     }
+
+    #[test]
+    fn buf_unterminated_final_entry() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let dedup = BufferDeduper::new(&"spam\nham\neggs", &mut output, Options::default());
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\nham\neggs", str::from_utf8(&output).unwrap());
+
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                add_terminator: true,
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&"spam\nham\neggs", &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\nham\neggs\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn buf_unterminated_final_duplicate() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let dedup = BufferDeduper::new(&"spam\nham\nspam", &mut output, Options::default());
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\nham\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn buf_breakfast_occurrences() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                occurrences: Some(CountPosition::Prefix),
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&BREAKFAST, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!(
+            "      2 spam\n      3 ham\n      2 eggs\n      1 ham eggs\n",
+            str::from_utf8(&output).unwrap()
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                occurrences: Some(CountPosition::Suffix),
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&BREAKFAST, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!(
+            "spam\t2\nham\t3\neggs\t2\nham eggs\t1\n",
+            str::from_utf8(&output).unwrap()
+        );
+    }
+
+    #[test]
+    fn buf_breakfast_filters() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                filter: Filter::Repeated,
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&BREAKFAST, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\nham\neggs\n", str::from_utf8(&output).unwrap());
+
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                filter: Filter::Unrepeated,
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&BREAKFAST, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("ham eggs\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn buf_breakfast_keep_last() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                keep: Keep::Last,
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&BREAKFAST, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("ham eggs\neggs\nham\nspam\n", str::from_utf8(&output).unwrap());
+
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                keep: Keep::Last,
+                occurrences: Some(CountPosition::Suffix),
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&BREAKFAST, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!(
+            "ham eggs\t1\neggs\t2\nham\t3\nspam\t2\n",
+            str::from_utf8(&output).unwrap()
+        );
+    }
+
+    static INVENTORY: &str = "\
+1,spam
+2,ham
+3,spam
+4,eggs
+5,ham
+";
+
+    #[test]
+    fn buf_key_field() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                key_fields: KeyFields::new(vec![2], b','),
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&INVENTORY, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("1,spam\n2,ham\n4,eggs\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn buf_key_range() {
+        let log = "\
+12:00:01 started
+12:00:02 started
+12:00:05 stopped
+";
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                key_range: Some(KeyRange {
+                    skip: 9,
+                    length: None,
+                    unit: RangeUnit::Bytes,
+                }),
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&log, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!(
+            "12:00:01 started\n12:00:05 stopped\n",
+            str::from_utf8(&output).unwrap()
+        );
+    }
+
+    static LOG: &str = "\
+12:00:01 login user=spam
+12:00:02 login user=ham
+12:00:03 restart
+12:00:04 login user=spam
+";
+
+    #[test]
+    fn buf_key_regex() {
+        let mut output: Vec<u8> = Vec::new();
+        let stats = {
+            let opts = Options {
+                key_regex: KeyRegex::new(Regex::new("user=(.*)").unwrap(), None).ok(),
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&LOG, &mut output, opts);
+            dedup.run().unwrap()
+        };
+        assert_eq!(
+            "12:00:01 login user=spam\n12:00:02 login user=ham\n12:00:03 restart\n",
+            str::from_utf8(&output).unwrap()
+        );
+        assert_eq!(stats.unique, 3);
+
+        let mut output: Vec<u8> = Vec::new();
+        let stats = {
+            let opts = Options {
+                key_regex: KeyRegex::new(Regex::new("user=(.*)").unwrap(), None).ok(),
+                unmatched: Unmatched::Drop,
+                keep: Keep::Last,
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&LOG, &mut output, opts);
+            dedup.run().unwrap()
+        };
+        assert_eq!(
+            "12:00:02 login user=ham\n12:00:04 login user=spam\n",
+            str::from_utf8(&output).unwrap()
+        );
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.duplicates(), 1);
+    }
+
+    #[test]
+    fn buf_ignore_case_normalized() {
+        let input = "Cafe\u{301}\nCAF\u{c9}\ncaf\u{e9}\nSpam\n";
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                case_folding: Some(CaseFolding::Unicode),
+                normalization: Some(Normalization::Nfc),
+                ..Options::default()
+            };
+            let dedup = BufferDeduper::new(&input, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("Cafe\u{301}\nSpam\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn buffer_multi_byte_delimiter() {
+        let input = "spam\r\nham\r\nham\nspam\r\nspam";
+        let opts = Options {
+            delim: b"\r\n".to_vec(),
+            ..Options::default()
+        };
+        let mut output = Vec::new();
+        {
+            let dedup = BufferDeduper::new(input, &mut output, opts.clone());
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\r\nham\r\nham\nspam\r\n", str::from_utf8(&output).unwrap());
+
+        let opts = Options {
+            keep: Keep::Last,
+            ..opts
+        };
+        let mut output = Vec::new();
+        {
+            let dedup = BufferDeduper::new(input, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("ham\r\nham\nspam\r\nspam", str::from_utf8(&output).unwrap());
+    }
+
+    static TRACES: &str = "\
+2018-03-01 ERROR connection lost
+  at spam.rs:10
+  at ham.rs:20
+2018-03-01 INFO reconnected
+2018-03-02 ERROR connection lost
+  at spam.rs:10
+  at ham.rs:20
+2018-03-02 ERROR connection lost
+  at eggs.rs:30
+";
+
+    #[test]
+    fn buffer_record_start() {
+        let opts = Options {
+            record_start: Some(Regex::new(r"(?m)^\d{4}-\d{2}-\d{2} ").unwrap()),
+            key_range: Some(KeyRange {
+                skip: 11,
+                length: None,
+                unit: RangeUnit::Bytes,
+            }),
+            ..Options::default()
+        };
+        let mut output = Vec::new();
+        let stats = {
+            let dedup = BufferDeduper::new(TRACES, &mut output, opts);
+            dedup.run().unwrap()
+        };
+        let expected = "\
+2018-03-01 ERROR connection lost
+  at spam.rs:10
+  at ham.rs:20
+2018-03-01 INFO reconnected
+2018-03-02 ERROR connection lost
+  at eggs.rs:30
+";
+        assert_eq!(expected, str::from_utf8(&output).unwrap());
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.unique, 3);
+    }
+
+    #[test]
+    fn buffer_several_inputs() {
+        let inputs = ["spam\nham", "ham\neggs\n", "eggs\nspam"];
+        let mut output = Vec::new();
+        let stats = {
+            let dedup = BufferDeduper::from_buffers(&inputs, &mut output, Options::default());
+            dedup.run().unwrap()
+        };
+        assert_eq!("spam\nham\neggs\n", str::from_utf8(&output).unwrap());
+        assert_eq!(stats.total, 6);
+        assert_eq!(stats.unique, 3);
+
+        let opts = Options {
+            keep: Keep::Last,
+            occurrences: Some(CountPosition::Suffix),
+            ..Options::default()
+        };
+        let mut output = Vec::new();
+        {
+            let dedup = BufferDeduper::from_buffers(&inputs, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("ham\t2\neggs\t2\nspam\t2", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn buffer_exclude() {
+        let seen = "ham\nbacon\n";
+        let counted = Options {
+            occurrences: Some(CountPosition::Prefix),
+            ..Options::default()
+        };
+        let expected = [
+            "spam\neggs\nham eggs\n",
+            "      2 spam\n      2 eggs\n      1 ham eggs\n",
+        ];
+        for (opts, expected) in vec![Options::default(), counted].into_iter().zip(&expected) {
+            let mut output = Vec::new();
+            let stats = {
+                let mut dedup = BufferDeduper::new(BREAKFAST, &mut output, opts);
+                dedup.exclude(seen);
+                dedup.run().unwrap()
+            };
+            assert_eq!(*expected, str::from_utf8(&output).unwrap());
+            assert_eq!(stats.total, 8);
+            assert_eq!(stats.unique, 3);
+            assert_eq!(stats.excluded, 3);
+            assert_eq!(stats.duplicates(), 2);
+        }
+    }
+
+    #[test]
+    fn buffer_set_operations() {
+        let inputs = ["spam\nham\neggs\nham\n", "eggs\nbacon\nham\n", "ham\neggs\nbeans\n"];
+        let cases = [
+            (SetOperation::Union, "spam\nham\neggs\nbacon\nbeans\n"),
+            (SetOperation::Intersection, "ham\neggs\n"),
+            (SetOperation::SymmetricDifference, "spam\nbacon\nbeans\n"),
+        ];
+        for &(set_operation, expected) in &cases {
+            let opts = Options {
+                set_operation,
+                ..Options::default()
+            };
+            let mut output = Vec::new();
+            let stats = {
+                let dedup = BufferDeduper::from_buffers(&inputs, &mut output, opts);
+                dedup.run().unwrap()
+            };
+            assert_eq!(expected, str::from_utf8(&output).unwrap());
+            assert_eq!(stats.unique, 5);
+        }
+
+        let opts = Options {
+            set_operation: SetOperation::Intersection,
+            occurrences: Some(CountPosition::Prefix),
+            keep: Keep::Last,
+            ..Options::default()
+        };
+        let mut output = Vec::new();
+        {
+            let dedup = BufferDeduper::from_buffers(&inputs, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("      4 ham\n      3 eggs\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn buffer_report() {
+        let inputs = ["spam\nham\neggs\nham\n", "bacon\nspam\nham"];
+        let report = {
+            let mut dedup = BufferDeduper::from_buffers(&inputs, io::sink(), Options::default());
+            dedup.exclude("bacon\n");
+            dedup.report()
+        };
+        let occurrence = |input, record, offset| Occurrence {
+            input,
+            record,
+            offset,
+        };
+        assert_eq!(
+            report.duplicates,
+            vec![
+                Duplicate {
+                    entry: b"spam",
+                    occurrences: vec![occurrence(0, 1, 0), occurrence(1, 2, 6)],
+                },
+                Duplicate {
+                    entry: b"ham",
+                    occurrences: vec![
+                        occurrence(0, 2, 5),
+                        occurrence(0, 4, 14),
+                        occurrence(1, 3, 11),
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn buffer_bloom_filter() {
+        for &keep in &[Keep::First, Keep::Last] {
+            let opts = Options {
+                keep,
+                ..Options::default()
+            };
+            let mut expected = Vec::new();
+            BufferDeduper::new(BREAKFAST, &mut expected, opts.clone()).run().unwrap();
+
+            let opts = Options {
+                bloom_filter: Some(BloomConfig {
+                    expected_keys: 100,
+                    false_positive_rate: 1e-9,
+                }),
+                ..opts
+            };
+            let mut output = Vec::new();
+            let stats = BufferDeduper::new(BREAKFAST, &mut output, opts).run().unwrap();
+            assert_eq!(str::from_utf8(&expected).unwrap(), str::from_utf8(&output).unwrap());
+            assert_eq!(stats.unique, 4);
+        }
+    }
+
+    #[test]
+    fn buffer_max_entries() {
+        let opts = Options {
+            max_entries: Some(3),
+            ..Options::default()
+        };
+        let mut output = Vec::new();
+        let stats = BufferDeduper::new(BREAKFAST, &mut output, opts).run().unwrap();
+        assert_eq!("spam\nham\neggs\nham eggs\nspam\n", str::from_utf8(&output).unwrap());
+        assert_eq!(stats.unique, 5);
+        assert_eq!(stats.evictions, 2);
+    }
 }
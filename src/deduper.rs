@@ -0,0 +1,464 @@
+use buffer::BufferDeduper;
+use error::DedupError;
+use key::{CaseFolding, KeyFields, KeyRange, KeyRegex, Normalization, Unmatched, Whitespace};
+use options::{CountPosition, Filter, Keep, Options, SetOperation, Window};
+use report::Report;
+use set::{BloomConfig, FingerprintSet};
+use stats::Stats;
+use stream::UnsortedStreamDeduper;
+
+use regex::bytes::Regex;
+
+use std::io;
+use std::path::PathBuf;
+
+/// A reusable, configurable entry point to the deduplication engines.
+///
+/// A `Deduper` is configured with builder-style methods and can then be run any number of times,
+/// either over an in-memory buffer (such as a memory mapped file) or over a buffered stream.
+///
+/// # Examples
+///
+/// ```
+/// use dedup::Deduper;
+///
+/// let input = b"spam\0ham\0spam\0";
+/// let mut output = Vec::new();
+///
+/// Deduper::new()
+///     .delimiter(b"\0")
+///     .run_buffer(input, &mut output)
+///     .unwrap();
+///
+/// assert_eq!(output, b"spam\0ham\0");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Deduper {
+    opts: Options,
+}
+
+impl Deduper {
+    /// Creates a new `Deduper` that separates entries by newlines.
+    pub fn new() -> Self {
+        Deduper::default()
+    }
+
+    /// Creates a new `Deduper` from an existing set of options.
+    pub fn with_options(opts: Options) -> Self {
+        Deduper { opts }
+    }
+
+    /// Sets the bytes that separate one entry from the next, such as `b"\r\n"` or `b"\n\n"` for
+    /// paragraphs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delim` is empty.
+    pub fn delimiter(&mut self, delim: &[u8]) -> &mut Self {
+        assert!(!delim.is_empty(), "the delimiter must not be empty");
+        self.opts.delim = delim.to_vec();
+        self
+    }
+
+    /// Sets a regular expression whose matches start a new entry, for entries that span several
+    /// lines, such as log messages followed by a stack trace. Use `(?m)^` to anchor matches to the
+    /// start of a line.
+    ///
+    /// The whole input must be held in memory to find where entries start, so `run_stream` reads
+    /// it all before deduplicating.
+    pub fn record_start(&mut self, regex: Option<Regex>) -> &mut Self {
+        self.opts.record_start = regex;
+        self
+    }
+
+    /// Sets whether a final entry that lacks a delimiter has one appended when it is written.
+    ///
+    /// Entries are compared without their delimiters either way, so a final `ham` is always
+    /// considered a duplicate of an earlier `ham\n`.
+    pub fn add_terminator(&mut self, yes: bool) -> &mut Self {
+        self.opts.add_terminator = yes;
+        self
+    }
+
+    /// Sets whether entries are only counted rather than written to the output.
+    pub fn count_only(&mut self, yes: bool) -> &mut Self {
+        self.opts.count_only = yes;
+        self
+    }
+
+    /// Sets whether each unique entry is written along with its number of occurrences, and where.
+    ///
+    /// Entries are still written in the order they first appear.
+    pub fn occurrences(&mut self, position: Option<CountPosition>) -> &mut Self {
+        self.opts.occurrences = position;
+        self
+    }
+
+    /// Sets which unique entries are written, based on how many times they occur.
+    pub fn filter(&mut self, filter: Filter) -> &mut Self {
+        self.opts.filter = filter;
+        self
+    }
+
+    /// Sets which occurrence of each entry is written, and therefore where it appears in the
+    /// output.
+    pub fn keep(&mut self, keep: Keep) -> &mut Self {
+        self.opts.keep = keep;
+        self
+    }
+
+    /// Sets which unique entries are written when deduplicating several inputs, based on how many
+    /// of them each entry occurs in. A single input is treated like a union.
+    pub fn set_operation(&mut self, operation: SetOperation) -> &mut Self {
+        self.opts.set_operation = operation;
+        self
+    }
+
+    /// Sets the fields that entries are compared by. If `None`, entries are compared in their
+    /// entirety. The whole entry is written either way.
+    pub fn key_fields(&mut self, fields: Option<KeyFields>) -> &mut Self {
+        self.opts.key_fields = fields;
+        self
+    }
+
+    /// Sets the range of bytes or characters that entries are compared by, applied after any key
+    /// fields. If `None`, the whole key is compared.
+    pub fn key_range(&mut self, range: Option<KeyRange>) -> &mut Self {
+        self.opts.key_range = range;
+        self
+    }
+
+    /// Sets the regular expression capture that entries are compared by, applied after any key
+    /// fields and range. If `None`, the whole key is compared.
+    pub fn key_regex(&mut self, regex: Option<KeyRegex>) -> &mut Self {
+        self.opts.key_regex = regex;
+        self
+    }
+
+    /// Sets what happens to entries that the key regular expression does not match.
+    pub fn unmatched(&mut self, unmatched: Unmatched) -> &mut Self {
+        self.opts.unmatched = unmatched;
+        self
+    }
+
+    /// Sets the rules for ignoring differences in whitespace when comparing entries.
+    pub fn whitespace(&mut self, whitespace: Whitespace) -> &mut Self {
+        self.opts.whitespace = whitespace;
+        self
+    }
+
+    /// Sets how keys are case folded. If `None`, entries differing in case are distinct.
+    pub fn case_folding(&mut self, folding: Option<CaseFolding>) -> &mut Self {
+        self.opts.case_folding = folding;
+        self
+    }
+
+    /// Sets the Unicode normalization form keys are converted to. If `None`, keys are compared
+    /// byte for byte.
+    pub fn normalization(&mut self, form: Option<Normalization>) -> &mut Self {
+        self.opts.normalization = form;
+        self
+    }
+
+    /// Sets whether `run_stream` remembers a 128-bit fingerprint of each key rather than the key
+    /// itself, accepting a negligible risk of collisions in exchange for memory. Buffers are never
+    /// copied, so the `run_buffers` methods are unaffected, as are `max_memory`, `record_start` and
+    /// modes that count occurrences or keep the last occurrence.
+    pub fn hash_only(&mut self, yes: bool) -> &mut Self {
+        self.opts.hash_only = yes;
+        self
+    }
+
+    /// Sets the parameters of a Bloom filter to remember seen keys in, so that memory use stays
+    /// fixed however long the input. Some unique entries are then discarded as duplicates, at the
+    /// configured false positive rate. If `None`, keys are remembered exactly.
+    ///
+    /// # Panics
+    ///
+    /// Running panics if the false positive rate is not between 0 and 1, exclusive.
+    pub fn bloom_filter(&mut self, config: Option<BloomConfig>) -> &mut Self {
+        self.opts.bloom_filter = config;
+        self
+    }
+
+    /// Sets the maximum number of keys to remember, beyond which the least recently seen key is
+    /// forgotten, so that memory use stays capped however many distinct entries there are.
+    /// Entries whose keys were forgotten are written again. If `None`, every key is remembered.
+    ///
+    /// # Panics
+    ///
+    /// Running panics if the maximum is 0.
+    pub fn max_entries(&mut self, entries: Option<usize>) -> &mut Self {
+        self.opts.max_entries = entries;
+        self
+    }
+
+    /// Sets the number of threads `run_buffer` deduplicates on, or 0 for one per CPU. Entries
+    /// are still written in the order they first appear. Modes that count occurrences, keep the
+    /// last occurrence, or remember keys in a `FingerprintSet`, Bloom filter or bounded set run
+    /// on a single thread regardless.
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        self.opts.threads = threads;
+        self
+    }
+
+    /// Sets a window within which `run_stream` suppresses repeated entries, forgetting each entry
+    /// once the window has passed since it was last seen. This bounds memory use when following a
+    /// live stream, and lets entries repeat after a while. Every entry is written as soon as it is
+    /// read, so running returns an error if occurrence counts, filters, set operations,
+    /// `Keep::Last`, `record_start`, `max_memory`, a Bloom filter, `max_entries` or `hash_only`
+    /// are also set. If `None`, entries are never forgotten.
+    pub fn window(&mut self, window: Option<Window>) -> &mut Self {
+        self.opts.window = window;
+        self
+    }
+
+    /// Sets the approximate number of bytes of entries `run_stream` may hold in memory. Beyond
+    /// that, entries are partitioned by key into temporary files, which are deduplicated one at
+    /// a time. If `None`, memory use is unbounded. Not applied along with `record_start`, which
+    /// reads the whole stream into memory.
+    pub fn max_memory(&mut self, bytes: Option<usize>) -> &mut Self {
+        self.opts.max_memory = bytes;
+        self
+    }
+
+    /// Sets the directory temporary files are created in once `max_memory` is exceeded. If
+    /// `None`, the system's temporary directory is used.
+    pub fn spill_dir(&mut self, dir: Option<PathBuf>) -> &mut Self {
+        self.opts.spill_dir = dir;
+        self
+    }
+
+    /// Returns the options this `Deduper` will run with.
+    pub fn options(&self) -> &Options {
+        &self.opts
+    }
+
+    /// Deduplicates the entries in `buffer`, writing the first occurrence of each to `output`.
+    ///
+    /// Returns statistics about the entries read.
+    pub fn run_buffer<R, W>(&self, buffer: &R, output: W) -> Result<Stats, DedupError>
+    where
+        R: AsRef<[u8]> + ?Sized,
+        W: io::Write,
+    {
+        BufferDeduper::new(buffer, output, self.opts.clone()).run()
+    }
+
+    /// Deduplicates the entries of each of `buffers` in turn, as though they were a single input,
+    /// writing the first occurrence of each to `output`.
+    ///
+    /// Returns statistics about the entries read.
+    pub fn run_buffers<'a, I, R, W>(&self, buffers: I, output: W) -> Result<Stats, DedupError>
+    where
+        I: IntoIterator<Item = &'a R>,
+        R: AsRef<[u8]> + ?Sized + 'a,
+        W: io::Write + 'a,
+    {
+        BufferDeduper::from_buffers(buffers, output, self.opts.clone()).run()
+    }
+
+    /// Like `run_buffers`, but entries that occur in any of `exclusions` are never written, as
+    /// though they had already been seen. Entries are compared by the same key as the input.
+    pub fn run_buffers_excluding<'a, I, E, R, W>(
+        &self,
+        buffers: I,
+        exclusions: E,
+        output: W,
+    ) -> Result<Stats, DedupError>
+    where
+        I: IntoIterator<Item = &'a R>,
+        E: IntoIterator<Item = &'a R>,
+        R: AsRef<[u8]> + ?Sized + 'a,
+        W: io::Write + 'a,
+    {
+        let mut dedup = BufferDeduper::from_buffers(buffers, output, self.opts.clone());
+        for exclusion in exclusions {
+            dedup.exclude(exclusion);
+        }
+        dedup.run()
+    }
+
+    /// Like `run_buffers_excluding`, but entries whose keys are in `state`, such as those seen by
+    /// earlier runs, are never written either. The key of every entry read that does not occur in
+    /// `exclusions` is added to `state`, which can then be
+    /// [saved](struct.FingerprintSet.html#method.save) for the next run.
+    pub fn run_buffers_with_state<'a, I, E, R, W>(
+        &self,
+        buffers: I,
+        exclusions: E,
+        state: &mut FingerprintSet,
+        output: W,
+    ) -> Result<Stats, DedupError>
+    where
+        I: IntoIterator<Item = &'a R>,
+        E: IntoIterator<Item = &'a R>,
+        R: AsRef<[u8]> + ?Sized + 'a,
+        W: io::Write + 'a,
+    {
+        let mut dedup = BufferDeduper::from_buffers(buffers, output, self.opts.clone());
+        for exclusion in exclusions {
+            dedup.exclude(exclusion);
+        }
+        dedup.run_with_state(state)
+    }
+
+    /// Finds where each duplicated entry of `buffers` occurs, leaving out entries that occur in any
+    /// of `exclusions`. Inputs are identified in the report by their index in `buffers`.
+    pub fn report_buffers<'a, I, E, R>(&self, buffers: I, exclusions: E) -> Report<'a>
+    where
+        I: IntoIterator<Item = &'a R>,
+        E: IntoIterator<Item = &'a R>,
+        R: AsRef<[u8]> + ?Sized + 'a,
+    {
+        let mut dedup = BufferDeduper::from_buffers(buffers, io::sink(), self.opts.clone());
+        for exclusion in exclusions {
+            dedup.exclude(exclusion);
+        }
+        dedup.report()
+    }
+
+    /// Deduplicates the entries read from `input`, writing the first occurrence of each to
+    /// `output`.
+    ///
+    /// Returns statistics about the entries read.
+    pub fn run_stream<R, W>(&self, input: R, output: W) -> Result<Stats, DedupError>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        UnsortedStreamDeduper::new(input, output, self.opts.clone()).run()
+    }
+
+    /// Like `run_stream`, but entries that occur in any of `exclusions` are never written, as
+    /// though they had already been seen. Entries are compared by the same key as the input.
+    pub fn run_stream_excluding<R, E, B, W>(
+        &self,
+        input: R,
+        exclusions: E,
+        output: W,
+    ) -> Result<Stats, DedupError>
+    where
+        R: io::BufRead,
+        E: IntoIterator<Item = B>,
+        B: AsRef<[u8]>,
+        W: io::Write,
+    {
+        let mut dedup = UnsortedStreamDeduper::new(input, output, self.opts.clone());
+        for exclusion in exclusions {
+            dedup.exclude(exclusion.as_ref());
+        }
+        dedup.run()
+    }
+
+    /// Like `run_stream_excluding`, but entries whose keys are in `state`, such as those seen by
+    /// earlier runs, are never written either. The key of every entry read that does not occur in
+    /// `exclusions` is added to `state`, which can then be
+    /// [saved](struct.FingerprintSet.html#method.save) for the next run.
+    ///
+    /// Returns an error if a [`window`](#method.window) is set, since a state never forgets a key.
+    pub fn run_stream_with_state<R, E, B, W>(
+        &self,
+        input: R,
+        exclusions: E,
+        state: &mut FingerprintSet,
+        output: W,
+    ) -> Result<Stats, DedupError>
+    where
+        R: io::BufRead,
+        E: IntoIterator<Item = B>,
+        B: AsRef<[u8]>,
+        W: io::Write,
+    {
+        let mut dedup = UnsortedStreamDeduper::new(input, output, self.opts.clone());
+        for exclusion in exclusions {
+            dedup.exclude(exclusion.as_ref());
+        }
+        dedup.run_with_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    static BREAKFAST: &str = "spam\tham\teggs\tham\tspam\t";
+    static BREAKFAST_DEDUP: &str = "spam\tham\teggs\t";
+
+    #[test]
+    fn deduper_buffer_and_stream_agree() {
+        let mut deduper = Deduper::new();
+        deduper.delimiter(b"\t");
+
+        let mut buf_output = Vec::new();
+        let buf_stats = deduper.run_buffer(BREAKFAST, &mut buf_output).unwrap();
+
+        let mut stream_output = Vec::new();
+        let reader = BufReader::new(BREAKFAST.as_bytes());
+        let stream_stats = deduper.run_stream(reader, &mut stream_output).unwrap();
+
+        assert_eq!(BREAKFAST_DEDUP.as_bytes(), &buf_output[..]);
+        assert_eq!(buf_output, stream_output);
+        assert_eq!(buf_stats, stream_stats);
+        assert_eq!(buf_stats.total, 5);
+        assert_eq!(buf_stats.unique, 3);
+        assert_eq!(buf_stats.duplicates(), 2);
+    }
+
+    #[test]
+    fn deduper_stream_record_start() {
+        let input = "> spam\nham\n> eggs\n> spam\nham\n> spam\n";
+        let mut deduper = Deduper::new();
+        deduper.record_start(Some(Regex::new("(?m)^> ").unwrap()));
+
+        let mut output = Vec::new();
+        let reader = BufReader::new(input.as_bytes());
+        let stats = deduper.run_stream(reader, &mut output).unwrap();
+
+        assert_eq!(&b"> spam\nham\n> eggs\n> spam\n"[..], &output[..]);
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.unique, 3);
+    }
+
+    #[test]
+    fn deduper_state() {
+        let mut deduper = Deduper::new();
+        deduper.delimiter(b"\t");
+        let mut state = FingerprintSet::new();
+        let no_exclusions: &[&str] = &[];
+
+        let mut output = Vec::new();
+        deduper
+            .run_buffers_with_state(&[BREAKFAST], no_exclusions, &mut state, &mut output)
+            .unwrap();
+        assert_eq!(BREAKFAST_DEDUP.as_bytes(), &output[..]);
+        assert_eq!(state.len(), 3);
+
+        let mut output = Vec::new();
+        let reader = BufReader::new("eggs\tbacon\tham\tbacon\t".as_bytes());
+        let stats = deduper
+            .run_stream_with_state(reader, no_exclusions, &mut state, &mut output)
+            .unwrap();
+        assert_eq!(&b"bacon\t"[..], &output[..]);
+        assert_eq!(stats.unique, 1);
+        assert_eq!(stats.duplicates(), 3);
+        assert_eq!(state.len(), 4);
+
+        deduper.occurrences(Some(CountPosition::Suffix));
+        let mut output = Vec::new();
+        let reader = BufReader::new("spam\tsausage\tsausage\t".as_bytes());
+        deduper
+            .run_stream_with_state(reader, no_exclusions, &mut state, &mut output)
+            .unwrap();
+        assert_eq!(&b"sausage\t2\t"[..], &output[..]);
+        assert_eq!(state.len(), 5);
+
+        deduper.occurrences(None);
+        deduper.window(Some(Window::Entries(2)));
+        let reader = BufReader::new("spam\t".as_bytes());
+        let result = deduper.run_stream_with_state(reader, no_exclusions, &mut state, io::sink());
+        assert!(result.is_err());
+        assert_eq!(state.len(), 5);
+    }
+}
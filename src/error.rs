@@ -1,11 +1,21 @@
 use std::fmt::{Display, Error, Formatter};
 use std::io;
 
+/// The error type for deduplication and argument parsing. Variants may be added in later
+/// releases.
 #[derive(Debug)]
 pub enum DedupError {
+    /// The output was closed before all entries were written.
     ClosedPipe,
+    /// A command-line argument could not be interpreted.
     ArgumentParseError(String),
+    /// A state file could not be loaded because it is damaged, truncated, or was written by an
+    /// unsupported version.
+    CorruptState(String),
+    /// Any other I/O failure.
     IO(io::Error),
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
 impl Display for DedupError {
@@ -14,6 +24,8 @@ impl Display for DedupError {
             DedupError::ClosedPipe => write!(f, "A closed pipe was encountered"),
             DedupError::IO(ref i) => write!(f, "{}", i),
             DedupError::ArgumentParseError(ref s) => write!(f, "{}", s),
+            DedupError::CorruptState(ref s) => write!(f, "Invalid state file: {}", s),
+            DedupError::__Nonexhaustive => unreachable!(),
         }
     }
 }
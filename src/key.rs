@@ -0,0 +1,480 @@
+use caseless::default_case_fold_str;
+use fastchr::FastchrSplit;
+use regex::bytes::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+use error::DedupError;
+
+use std::borrow::Cow;
+use std::str;
+
+/// Selects the fields of an entry that it is compared by, instead of the whole entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFields {
+    /// Sorted, non-overlapping and non-adjacent inclusive ranges of 1-based field indices.
+    ranges: Vec<(usize, usize)>,
+    separator: u8,
+}
+
+impl KeyFields {
+    /// Creates a selection of the given 1-based field `indices`, where fields are separated by
+    /// `separator`.
+    ///
+    /// Returns `None` if no indices are given or if any index is zero.
+    pub fn new(indices: Vec<usize>, separator: u8) -> Option<Self> {
+        KeyFields::from_ranges(indices.into_iter().map(|index| (index, index)).collect(), separator)
+    }
+
+    /// Creates a selection of the given inclusive ranges of 1-based field indices, where fields
+    /// are separated by `separator`. A range may end at `usize::max_value()` to select every field
+    /// from its start on.
+    ///
+    /// Returns `None` if no ranges are given, if any range starts at zero, or if any range ends
+    /// before it starts.
+    pub fn from_ranges(mut ranges: Vec<(usize, usize)>, separator: u8) -> Option<Self> {
+        if ranges.is_empty() || ranges.iter().any(|&(start, end)| start == 0 || end < start) {
+            return None;
+        }
+        ranges.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Some(KeyFields {
+            ranges: merged,
+            separator,
+        })
+    }
+
+    /// Returns the selected fields of `body`, joined by the separator.
+    ///
+    /// Fields missing from `body` are treated as empty. Trailing empty fields are left out of the
+    /// key, so that it doesn't depend on how many fields are missing.
+    pub fn select<'a>(&self, body: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut fields = FastchrSplit::new(self.separator, body);
+        if let [(start, end)] = self.ranges[..] {
+            if start == end {
+                return Cow::Borrowed(fields.nth(start - 1).unwrap_or(&[]));
+            }
+        }
+
+        let mut key = Vec::with_capacity(body.len());
+        let mut ranges = self.ranges.iter();
+        let mut range = ranges.next();
+        for (index, field) in (1..).zip(fields) {
+            while range.map_or(false, |&(_, end)| end < index) {
+                range = ranges.next();
+            }
+            match range {
+                Some(&(start, _)) if start <= index => {
+                    key.extend_from_slice(field);
+                    key.push(self.separator);
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        while key.last() == Some(&self.separator) {
+            key.pop();
+        }
+        Cow::Owned(key)
+    }
+}
+
+/// Restricts the part of an entry that it is compared by to a range of bytes or characters, in the
+/// style of `uniq -s` and `uniq -w`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRange {
+    /// The number of leading units to ignore.
+    pub skip: usize,
+    /// The maximum number of units to compare after those skipped. If `None`, the rest of the
+    /// entry is compared.
+    pub length: Option<usize>,
+    /// Whether the range is measured in bytes or in characters.
+    pub unit: RangeUnit,
+}
+
+/// The unit a [`KeyRange`](struct.KeyRange.html) is measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeUnit {
+    /// Each byte counts as one unit.
+    Bytes,
+    /// Each UTF-8 encoded character counts as one unit. Bytes that are not valid UTF-8 count as
+    /// one unit each.
+    Chars,
+}
+
+impl KeyRange {
+    /// Returns the part of `key` that falls within the range.
+    pub fn narrow<'a>(&self, key: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        let start = self.offset(&key, self.skip);
+        let end = match self.length {
+            Some(length) => start + self.offset(&key[start..], length),
+            None => key.len(),
+        };
+        slice_key(key, start, end)
+    }
+
+    /// Returns the byte offset just past the first `count` units of `key`, or the length of `key`
+    /// if it is shorter than that.
+    fn offset(&self, key: &[u8], count: usize) -> usize {
+        match self.unit {
+            RangeUnit::Bytes => count.min(key.len()),
+            RangeUnit::Chars => {
+                let mut offset = 0;
+                for _ in 0..count {
+                    if offset == key.len() {
+                        break;
+                    }
+                    offset += char_len(&key[offset..]);
+                }
+                offset
+            }
+        }
+    }
+}
+
+/// The length of the character at the start of `bytes`, or 1 if they don't start with valid UTF-8.
+fn char_len(bytes: &[u8]) -> usize {
+    let prefix = &bytes[..bytes.len().min(4)];
+    let valid = match str::from_utf8(prefix) {
+        Ok(valid) => valid,
+        Err(e) => str::from_utf8(&prefix[..e.valid_up_to()]).unwrap(),
+    };
+    valid.chars().next().map_or(1, char::len_utf8)
+}
+
+/// Compares entries by a capture group of a regular expression.
+#[derive(Debug, Clone)]
+pub struct KeyRegex {
+    regex: Regex,
+    group: usize,
+}
+
+impl KeyRegex {
+    /// Creates a key from a capture group of `regex`. The group may be given by number or by name.
+    /// If no group is given, the first capture group is used, or the whole match if `regex` has no
+    /// capture groups.
+    pub fn new(regex: Regex, group: Option<&str>) -> Result<Self, DedupError> {
+        let group = match group {
+            None => if regex.captures_len() > 1 { 1 } else { 0 },
+            Some(g) => match g.parse::<usize>() {
+                Ok(i) if i < regex.captures_len() => i,
+                Ok(_) => return Err(missing_group(&regex, g)),
+                Err(_) => regex
+                    .capture_names()
+                    .position(|name| name == Some(g))
+                    .ok_or_else(|| missing_group(&regex, g))?,
+            },
+        };
+        Ok(KeyRegex { regex, group })
+    }
+
+    /// Returns the captured part of `key`, or `None` if the regular expression does not match or
+    /// the group did not participate in the match.
+    pub fn capture<'a>(&self, key: Cow<'a, [u8]>) -> Option<Cow<'a, [u8]>> {
+        let (start, end) = if self.group == 0 {
+            let m = self.regex.find(&key)?;
+            (m.start(), m.end())
+        } else {
+            let m = self.regex.captures(&key)?.get(self.group)?;
+            (m.start(), m.end())
+        };
+        Some(slice_key(key, start, end))
+    }
+}
+
+fn missing_group(regex: &Regex, group: &str) -> DedupError {
+    DedupError::ArgumentParseError(format!(
+        "Capture group {} does not exist in key regex: {}",
+        group, regex
+    ))
+}
+
+/// Selects what happens to entries that a [`KeyRegex`](struct.KeyRegex.html) does not match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmatched {
+    /// The entry is written as though it were distinct from every other entry.
+    Pass,
+    /// The entry is discarded.
+    Drop,
+}
+
+/// Rules for ignoring differences in whitespace when comparing entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Whitespace {
+    /// Ignores leading and trailing whitespace.
+    pub trim: bool,
+    /// Treats every run of whitespace as a single space.
+    pub collapse: bool,
+    /// Ignores a single trailing carriage return, so that CRLF and LF line endings compare equal.
+    pub ignore_trailing_cr: bool,
+}
+
+impl Whitespace {
+    /// Returns `key` with the rules applied.
+    pub fn apply<'a>(&self, key: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        let mut key = key;
+        if self.ignore_trailing_cr && key.last() == Some(&b'\r') {
+            let end = key.len() - 1;
+            key = slice_key(key, 0, end);
+        }
+        if self.trim {
+            let start = key.iter().position(|b| !b.is_ascii_whitespace());
+            let end = key.iter().rposition(|b| !b.is_ascii_whitespace());
+            key = match (start, end) {
+                (Some(start), Some(end)) => slice_key(key, start, end + 1),
+                _ => slice_key(key, 0, 0),
+            };
+        }
+        if self.collapse && needs_collapse(&key) {
+            let mut collapsed = Vec::with_capacity(key.len());
+            let mut in_run = false;
+            for &b in key.iter() {
+                if !b.is_ascii_whitespace() {
+                    collapsed.push(b);
+                    in_run = false;
+                } else if !in_run {
+                    collapsed.push(b' ');
+                    in_run = true;
+                }
+            }
+            key = Cow::Owned(collapsed);
+        }
+        key
+    }
+}
+
+/// Whether `key` contains whitespace other than single spaces.
+fn needs_collapse(key: &[u8]) -> bool {
+    let mut prev_whitespace = false;
+    for &b in key {
+        let whitespace = b.is_ascii_whitespace();
+        if whitespace && (prev_whitespace || b != b' ') {
+            return true;
+        }
+        prev_whitespace = whitespace;
+    }
+    false
+}
+
+/// Selects how keys are case folded so that entries differing only in case compare equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseFolding {
+    /// Only the ASCII letters `A` to `Z` are folded.
+    Ascii,
+    /// Full Unicode case folding is applied to keys that are valid UTF-8. Other keys are folded as
+    /// ASCII.
+    Unicode,
+}
+
+impl CaseFolding {
+    /// Returns the case folded form of `key`.
+    pub fn fold<'a>(&self, key: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        if *self == CaseFolding::Unicode && !key.is_ascii() {
+            if let Ok(s) = str::from_utf8(&key) {
+                return Cow::Owned(default_case_fold_str(s).into_bytes());
+            }
+        }
+
+        if !key.iter().any(u8::is_ascii_uppercase) {
+            return key;
+        }
+        let mut key = key.into_owned();
+        key.make_ascii_lowercase();
+        Cow::Owned(key)
+    }
+}
+
+/// Selects the Unicode normalization form keys are converted to, so that canonically or
+/// compatibly equivalent entries compare equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Normalization Form C, canonical composition.
+    Nfc,
+    /// Normalization Form KC, compatibility composition.
+    Nfkc,
+}
+
+impl Normalization {
+    /// Returns the normalized form of `key`. Keys that are not valid UTF-8 are left unchanged.
+    pub fn normalize<'a>(&self, key: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        if key.is_ascii() {
+            return key;
+        }
+        let normalized: String = match str::from_utf8(&key) {
+            Ok(s) => match *self {
+                Normalization::Nfc => s.nfc().collect(),
+                Normalization::Nfkc => s.nfkc().collect(),
+            },
+            Err(_) => return key,
+        };
+        Cow::Owned(normalized.into_bytes())
+    }
+}
+
+fn slice_key<'a>(key: Cow<'a, [u8]>, start: usize, end: usize) -> Cow<'a, [u8]> {
+    match key {
+        Cow::Borrowed(k) => Cow::Borrowed(&k[start..end]),
+        Cow::Owned(mut k) => {
+            k.truncate(end);
+            k.drain(..start);
+            Cow::Owned(k)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_single_field() {
+        let fields = KeyFields::new(vec![2], b',').unwrap();
+        assert_eq!(&fields.select(b"17,spam,ham")[..], b"spam");
+        assert_eq!(&fields.select(b"17,,ham")[..], b"");
+        assert_eq!(&fields.select(b"17")[..], b"");
+    }
+
+    #[test]
+    fn select_multiple_fields() {
+        let fields = KeyFields::new(vec![3, 1], b'\t').unwrap();
+        assert_eq!(&fields.select(b"17\tspam\tham\teggs")[..], b"17\tham");
+        assert_eq!(&fields.select(b"17\tspam")[..], b"17");
+        assert_eq!(&fields.select(b"17\tspam\t")[..], b"17");
+    }
+
+    #[test]
+    fn select_field_ranges() {
+        let fields = KeyFields::from_ranges(vec![(4, 5), (2, 2)], b',').unwrap();
+        assert_eq!(fields, KeyFields::new(vec![2, 4, 5], b',').unwrap());
+        assert_eq!(&fields.select(b"1,2,3,4,5,6")[..], b"2,4,5");
+
+        let fields = KeyFields::from_ranges(vec![(2, usize::max_value())], b',').unwrap();
+        assert_eq!(&fields.select(b"1,2,3,4,5,6")[..], b"2,3,4,5,6");
+        assert_eq!(&fields.select(b"1")[..], b"");
+
+        let fields = KeyFields::from_ranges(vec![(1, 1), (4_000_000_000, 4_000_000_001)], b',');
+        assert_eq!(&fields.unwrap().select(b"1,2,3")[..], b"1");
+    }
+
+    #[test]
+    fn narrow_bytes() {
+        let range = KeyRange {
+            skip: 2,
+            length: Some(3),
+            unit: RangeUnit::Bytes,
+        };
+        assert_eq!(&range.narrow(Cow::Borrowed(b"17spam"))[..], b"spa");
+        assert_eq!(&range.narrow(Cow::Owned(b"17spam".to_vec()))[..], b"spa");
+        assert_eq!(&range.narrow(Cow::Borrowed(b"17sp"))[..], b"sp");
+        assert_eq!(&range.narrow(Cow::Borrowed(b"1"))[..], b"");
+    }
+
+    #[test]
+    fn narrow_chars() {
+        let range = KeyRange {
+            skip: 1,
+            length: Some(2),
+            unit: RangeUnit::Chars,
+        };
+        assert_eq!(&range.narrow(Cow::Borrowed("»smörgås".as_bytes()))[..], b"sm");
+        assert_eq!(&range.narrow(Cow::Borrowed("össe".as_bytes()))[..], b"ss");
+        assert_eq!(&range.narrow(Cow::Borrowed("sös".as_bytes()))[..], "ös".as_bytes());
+    }
+
+    #[test]
+    fn narrow_invalid_utf8() {
+        let range = KeyRange {
+            skip: 1,
+            length: Some(2),
+            unit: RangeUnit::Chars,
+        };
+        // Stray continuation bytes, invalid bytes and incomplete characters are one unit each.
+        assert_eq!(&range.narrow(Cow::Borrowed(b"\x80spam"))[..], b"sp");
+        assert_eq!(&range.narrow(Cow::Borrowed(b"s\x80\x80pam"))[..], b"\x80\x80");
+        assert_eq!(&range.narrow(Cow::Borrowed(b"\xffs\xc3(am"))[..], b"s\xc3");
+        assert_eq!(&range.narrow(Cow::Borrowed(b"\xe2\x82s\xe2"))[..], b"\x82s");
+    }
+
+    #[test]
+    fn capture_groups() {
+        let regex = Regex::new(r"user=(\w+) id=(?P<id>\d+)").unwrap();
+
+        let first = KeyRegex::new(regex.clone(), None).unwrap();
+        let key = first.capture(Cow::Borrowed(b"12:00 user=spam id=17 ok"));
+        assert_eq!(&key.unwrap()[..], b"spam");
+
+        let named = KeyRegex::new(regex.clone(), Some("id")).unwrap();
+        let key = named.capture(Cow::Owned(b"12:00 user=spam id=17 ok".to_vec()));
+        assert_eq!(&key.unwrap()[..], b"17");
+
+        let whole = KeyRegex::new(regex.clone(), Some("0")).unwrap();
+        let key = whole.capture(Cow::Borrowed(b"12:00 user=spam id=17 ok"));
+        assert_eq!(&key.unwrap()[..], b"user=spam id=17");
+
+        assert!(first.capture(Cow::Borrowed(b"12:00 no user")).is_none());
+        assert!(KeyRegex::new(regex.clone(), Some("3")).is_err());
+        assert!(KeyRegex::new(regex, Some("name")).is_err());
+    }
+
+    #[test]
+    fn whitespace_rules() {
+        let cr = Whitespace {
+            ignore_trailing_cr: true,
+            ..Whitespace::default()
+        };
+        assert_eq!(&cr.apply(Cow::Borrowed(b" spam\r"))[..], b" spam");
+        assert_eq!(&cr.apply(Cow::Borrowed(b" spam\r\r"))[..], b" spam\r");
+
+        let trim = Whitespace {
+            trim: true,
+            ..Whitespace::default()
+        };
+        assert_eq!(&trim.apply(Cow::Borrowed(b" \tspam  ham \r"))[..], b"spam  ham");
+        assert_eq!(&trim.apply(Cow::Borrowed(b" \t "))[..], b"");
+
+        let collapse = Whitespace {
+            collapse: true,
+            ..Whitespace::default()
+        };
+        assert_eq!(&collapse.apply(Cow::Borrowed(b" spam \t ham\t"))[..], b" spam ham ");
+        assert!(match collapse.apply(Cow::Borrowed(b"spam ham")) {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        });
+    }
+
+    #[test]
+    fn case_folding() {
+        let ascii = CaseFolding::Ascii;
+        assert_eq!(&ascii.fold(Cow::Borrowed(b"SpAm"))[..], b"spam");
+        let folded = ascii.fold(Cow::Borrowed("STRASSE ÆØ".as_bytes()));
+        assert_eq!(&folded[..], "strasse ÆØ".as_bytes());
+
+        let unicode = CaseFolding::Unicode;
+        assert_eq!(&unicode.fold(Cow::Borrowed("Straße".as_bytes()))[..], b"strasse");
+        assert_eq!(&unicode.fold(Cow::Borrowed("ÆØ".as_bytes()))[..], "æø".as_bytes());
+        assert_eq!(&unicode.fold(Cow::Borrowed(b"SP\xffM"))[..], b"sp\xffm");
+    }
+
+    #[test]
+    fn normalization() {
+        let decomposed = "Cafe\u{301}".as_bytes();
+        let composed = "Caf\u{e9}".as_bytes();
+        assert_eq!(&Normalization::Nfc.normalize(Cow::Borrowed(decomposed))[..], composed);
+        let ligature = "ﬁ".as_bytes();
+        assert_eq!(&Normalization::Nfc.normalize(Cow::Borrowed(ligature))[..], ligature);
+        assert_eq!(&Normalization::Nfkc.normalize(Cow::Borrowed(ligature))[..], b"fi");
+    }
+
+    #[test]
+    fn invalid_fields() {
+        assert!(KeyFields::new(vec![], b'\t').is_none());
+        assert!(KeyFields::new(vec![0, 1], b'\t').is_none());
+        assert!(KeyFields::from_ranges(vec![(3, 1)], b'\t').is_none());
+    }
+}
@@ -0,0 +1,56 @@
+//! `dedup` removes repeated entries from text and binary data while preserving the order in which
+//! entries first appear. No sorting is required.
+//!
+//! The [`Deduper`](struct.Deduper.html) builder is the simplest way to use this crate. It can run
+//! over an in-memory buffer, which is the fastest option and works well with memory mapped
+//! files, or over any `BufRead` stream.
+//!
+//! ```
+//! use dedup::Deduper;
+//!
+//! let input = "spam\nham\neggs\nham\nspam\n";
+//! let mut output = Vec::new();
+//!
+//! Deduper::new().run_buffer(input, &mut output).unwrap();
+//!
+//! assert_eq!(output, b"spam\nham\neggs\n");
+//! ```
+//!
+//! The underlying engines, [`BufferDeduper`](struct.BufferDeduper.html) and
+//! [`UnsortedStreamDeduper`](struct.UnsortedStreamDeduper.html), are also exported for callers
+//! that want to manage them directly.
+
+#![warn(missing_docs)]
+
+extern crate caseless;
+extern crate fastchr;
+extern crate fxhash;
+extern crate rayon;
+extern crate regex;
+extern crate siphasher;
+extern crate unicode_normalization;
+
+mod buffer;
+mod deduper;
+mod error;
+mod key;
+mod options;
+mod parallel;
+mod records;
+mod report;
+mod set;
+mod spill;
+mod state;
+mod stats;
+mod stream;
+
+pub use buffer::BufferDeduper;
+pub use deduper::Deduper;
+pub use error::DedupError;
+pub use key::{CaseFolding, KeyFields, KeyRange, KeyRegex, Normalization, RangeUnit, Unmatched,
+              Whitespace};
+pub use options::{CountPosition, Filter, Keep, Options, SetOperation, Window};
+pub use report::{Duplicate, Occurrence, Report};
+pub use set::{BloomConfig, BloomFilter, ExpiringSet, FingerprintSet, KeySet, LruSet, Map, Set};
+pub use stats::Stats;
+pub use stream::UnsortedStreamDeduper;
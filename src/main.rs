@@ -1,27 +1,20 @@
 #[macro_use]
 extern crate clap;
-extern crate memchr;
+extern crate dedup;
 extern crate memmap;
-extern crate fxhash;
-extern crate fastchr;
+extern crate regex;
 
 use memmap::Mmap;
 
-use args::Args;
-use error::DedupError;
-use buffer::BufferDeduper;
-use stream::UnsortedStreamDeduper;
+use args::{Args, ReportFormat};
+use dedup::{DedupError, FingerprintSet, Stats};
 
-use std::io::{self, BufWriter, Read};
-use std::fs::{File, OpenOptions};
-use std::path::Path;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
 use std::process;
 
-mod buffer;
-mod error;
 mod args;
-mod stream;
-mod set;
 
 fn main() {
     match Args::parse().and_then(run) {
@@ -34,58 +27,276 @@ fn main() {
     };
 }
 
-fn run(args: Args) -> Result<u64, DedupError> {
-    if args.input.is_some() {
-        run_on_file(args)
+fn run(args: Args) -> Result<Stats, DedupError> {
+    // Every input is opened or loaded before the output is created, so that an existing output
+    // file is left untouched if one of them can't be read.
+    if let Some(ref p) = args.output {
+        check_output(p, &args)?;
+    }
+    let exclusions = load_inputs(&args.exclusions, args.mmap)?;
+    let state = match args.state {
+        Some(ref path) => Some(load_state(path)?),
+        None => None,
+    };
+    let inputs = Inputs::open(&args)?;
+
+    if let Some(ref p) = args.output {
+        let output = OpenOptions::new().write(true).create(true).truncate(true).open(p)?;
+        run_with_output(&args, &exclusions, inputs, state, BufWriter::new(output))
     } else {
-        run_on_stdin(args)
+        let out = io::stdout();
+        let output = BufWriter::new(out.lock());
+        run_with_output(&args, &exclusions, inputs, state, output)
     }
 }
 
-fn run_on_file(args: Args) -> Result<u64, DedupError> {
-    if args.mmap {
-        let input = memmap_file(args.input.as_ref().unwrap())?;
-        if let Some(ref p) = args.output {
-            let output = OpenOptions::new().write(true).create(true).open(p)?;
-            let output = BufWriter::new(output);
-            let dedup = BufferDeduper::new(&input, output, (&args).into());
-            dedup.run()
-        } else {
-            let out = io::stdout();
-            let output = BufWriter::new(out.lock());
-            let dedup = BufferDeduper::new(&input, output, args.into());
-            dedup.run()
+fn run_with_output<W: Write>(
+    args: &Args,
+    exclusions: &[Input],
+    inputs: Inputs,
+    mut state: Option<FingerprintSet>,
+    mut output: W,
+) -> Result<Stats, DedupError> {
+    let deduper = args.deduper();
+    let stats = match inputs {
+        Inputs::Stdin => {
+            let _input = io::stdin();
+            let input = _input.lock();
+            match state {
+                Some(ref mut state) => {
+                    deduper.run_stream_with_state(input, exclusions, state, &mut output)?
+                }
+                None => deduper.run_stream_excluding(input, exclusions, &mut output)?,
+            }
         }
-    } else {
-        let input = read_file_to_vec(args.input.as_ref().unwrap())?;
-        if let Some(ref p) = args.output {
-            let output = OpenOptions::new().write(true).create(true).open(p)?;
-            let output = BufWriter::new(output);
-            let dedup = BufferDeduper::new(&input, output, (&args).into());
-            dedup.run()
-        } else {
-            let out = io::stdout();
-            let output = BufWriter::new(out.lock());
-            let dedup = BufferDeduper::new(&input, output, args.into());
-            dedup.run()
+        Inputs::Stream(input) => {
+            let input = BufReader::with_capacity(1 << 16, input);
+            deduper.run_stream_excluding(input, exclusions, &mut output)?
+        }
+        Inputs::Buffers(ref buffers) => match (args.report, state.as_mut()) {
+            (Some(format), _) => return write_report(args, format, buffers, exclusions, output),
+            (None, Some(state)) => {
+                deduper.run_buffers_with_state(buffers, exclusions, state, &mut output)?
+            }
+            (None, None) => deduper.run_buffers_excluding(buffers, exclusions, &mut output)?,
+        },
+    };
+    if let (Some(path), Some(state)) = (args.state.as_ref(), state) {
+        // The state is only saved once every unique entry has been written.
+        output.flush()?;
+        state.save(path)?;
+    }
+
+    if args.count {
+        write!(output, "{}", stats)?;
+    }
+    if args.verbose {
+        write_verbose(args, &stats)?;
+    }
+    Ok(stats)
+}
+
+/// Fails if `path` is one of the inputs, since creating the output would destroy it before it
+/// was read.
+fn check_output(path: &Path, args: &Args) -> Result<(), DedupError> {
+    let output = match fs::canonicalize(path) {
+        Ok(output) => output,
+        // An output that doesn't exist yet can't be an input.
+        Err(_) => return Ok(()),
+    };
+    let inputs = args.inputs.iter().chain(&args.exclusions).filter_map(Option::as_ref);
+    for input in inputs {
+        if fs::canonicalize(input).ok().as_ref() == Some(&output) {
+            return Err(DedupError::ArgumentParseError(format!(
+                "The output file {} is also an input.",
+                path.display()
+            )));
         }
     }
+    Ok(())
+}
+
+fn write_verbose(args: &Args, stats: &Stats) -> Result<(), io::Error> {
+    let stderr = io::stderr();
+    let mut out = stderr.lock();
+    write!(out, "{}", stats)?;
+    // Fingerprints are only used when reading a stream, since mapped inputs aren't copied anyway.
+    if args.hash_only && stats.bytes_saved > 0 {
+        writeln!(out, "fingerprints: {}", stats.unique)?;
+        writeln!(out, "bytes saved: {}", stats.bytes_saved)?;
+        writeln!(
+            out,
+            "collision probability: {:e}",
+            FingerprintSet::collision_probability(stats.unique)
+        )?;
+    }
+    if let Some(config) = args.bloom_filter {
+        writeln!(out, "filter bytes: {}", config.size_in_bytes())?;
+        writeln!(out, "filter hashes: {}", config.hashes())?;
+    }
+    Ok(())
 }
 
-fn run_on_stdin(args: Args) -> Result<u64, DedupError> {
-    let _input = io::stdin();
-    let input = _input.lock();
+fn write_report<W: Write>(
+    args: &Args,
+    format: ReportFormat,
+    buffers: &[Input],
+    exclusions: &[Input],
+    mut output: W,
+) -> Result<Stats, DedupError> {
+    let names: Vec<_> = report_paths(args)
+        .iter()
+        .map(|input| input.as_ref().map_or("-".into(), |p| p.to_string_lossy()))
+        .collect();
 
-    if let Some(ref p) = args.output {
-        let output = OpenOptions::new().write(true).create(true).open(p)?;
-        let output = BufWriter::new(output);
-        let dedup = UnsortedStreamDeduper::new(input, output, (&args).into());
-        dedup.run()
+    let report = args.deduper().report_buffers(buffers, exclusions);
+    match format {
+        ReportFormat::Text => report.write_text(&mut output, &names)?,
+        ReportFormat::Json => report.write_json(&mut output, &names)?,
+    }
+    Ok(Stats::default())
+}
+
+/// The inputs a report covers, which is stdin if no inputs were given.
+fn report_paths(args: &Args) -> &[Option<PathBuf>] {
+    if args.inputs.is_empty() {
+        &[None]
     } else {
-        let out = io::stdout();
-        let output = out.lock();
-        let dedup = UnsortedStreamDeduper::new(input, output, args.into());
-        dedup.run()
+        &args.inputs[..]
+    }
+}
+
+/// Loads the state file at `path`, or an empty state if there is no file yet.
+fn load_state(path: &Path) -> Result<FingerprintSet, DedupError> {
+    match FingerprintSet::load(path) {
+        Err(DedupError::IO(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+            Ok(FingerprintSet::new())
+        }
+        result => result,
+    }
+}
+
+/// The inputs to deduplicate, opened or loaded ahead of the run.
+enum Inputs {
+    /// Stdin, read as a stream.
+    Stdin,
+    /// Every input in turn, read as a single stream.
+    Stream(Box<dyn Read>),
+    /// Every input, loaded in full.
+    Buffers(Vec<Input>),
+}
+
+impl Inputs {
+    fn open(args: &Args) -> Result<Self, io::Error> {
+        if args.report.is_some() {
+            // A report lists every input by name, so even stdin is loaded in full.
+            return load_inputs(report_paths(args), args.mmap).map(Inputs::Buffers);
+        }
+        match args.inputs[..] {
+            [] | [None] => Ok(Inputs::Stdin),
+            // Loading every input would defeat bounded memory use, so they're read one by one.
+            ref paths if args.max_memory.is_some() || args.window.is_some() => {
+                open_stream(paths, &args.delim).map(Inputs::Stream)
+            }
+            // Every input stays loaded until the end, since unique entries borrow from them.
+            ref paths => load_inputs(paths, args.mmap).map(Inputs::Buffers),
+        }
+    }
+}
+
+/// An input file, memory mapped or read into memory.
+enum Input {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl AsRef<[u8]> for Input {
+    fn as_ref(&self) -> &[u8] {
+        match *self {
+            Input::Mapped(ref mmap) => mmap,
+            Input::Read(ref buf) => buf,
+        }
+    }
+}
+
+fn load_inputs(paths: &[Option<PathBuf>], mmap: bool) -> Result<Vec<Input>, io::Error> {
+    paths
+        .iter()
+        .map(|path| load_input(path.as_ref(), mmap))
+        .collect()
+}
+
+/// Loads the file at `path`, or all of stdin if there is no path.
+fn load_input(path: Option<&PathBuf>, mmap: bool) -> Result<Input, io::Error> {
+    match path {
+        // Empty files can't be memory mapped.
+        Some(p) if mmap && fs::metadata(p)?.len() > 0 => memmap_file(p).map(Input::Mapped),
+        Some(p) => read_file_to_vec(p).map(Input::Read),
+        None => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            Ok(Input::Read(buf))
+        }
+    }
+}
+
+/// Opens every input as a single stream, with inputs that don't end with `delim` followed by it
+/// so that entries at their boundaries don't run together.
+fn open_stream(paths: &[Option<PathBuf>], delim: &[u8]) -> Result<Box<dyn Read>, io::Error> {
+    let mut stream: Box<dyn Read> = Box::new(io::empty());
+    for (i, path) in paths.iter().enumerate() {
+        let input: Box<dyn Read> = match *path {
+            Some(ref p) => Box::new(File::open(p)?),
+            None => Box::new(io::stdin()),
+        };
+        stream = if i + 1 < paths.len() {
+            Box::new(stream.chain(Terminated::new(input, delim)))
+        } else {
+            Box::new(stream.chain(input))
+        };
+    }
+    Ok(stream)
+}
+
+/// A reader that appends a delimiter to its input if the input is not empty and doesn't already
+/// end with one.
+struct Terminated<R> {
+    inner: R,
+    delim: Vec<u8>,
+    /// The last bytes read from `inner`, up to the length of the delimiter.
+    tail: Vec<u8>,
+    /// How much of the delimiter has been read, once `inner` is exhausted.
+    appended: Option<usize>,
+}
+
+impl<R: Read> Terminated<R> {
+    fn new(inner: R, delim: &[u8]) -> Self {
+        Terminated {
+            inner,
+            delim: delim.to_vec(),
+            tail: Vec::with_capacity(delim.len() * 2),
+            appended: None,
+        }
+    }
+}
+
+impl<R: Read> Read for Terminated<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.appended.is_none() {
+            let n = self.inner.read(buf)?;
+            if n > 0 {
+                self.tail.extend_from_slice(&buf[n.saturating_sub(self.delim.len())..n]);
+                let excess = self.tail.len().saturating_sub(self.delim.len());
+                self.tail.drain(..excess);
+                return Ok(n);
+            }
+            let terminated = self.tail.is_empty() || self.tail.ends_with(&self.delim);
+            self.appended = Some(if terminated { self.delim.len() } else { 0 });
+        }
+        let appended = self.appended.as_mut().unwrap();
+        let n = (&self.delim[*appended..]).read(buf)?;
+        *appended += n;
+        Ok(n)
     }
 }
 
@@ -0,0 +1,231 @@
+use set::BloomConfig;
+use key::{CaseFolding, KeyFields, KeyRange, KeyRegex, Normalization, Unmatched, Whitespace};
+use regex::bytes::Regex;
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Settings shared by every deduplication engine.
+///
+/// Most users should configure these through [`Deduper`](struct.Deduper.html) rather than
+/// constructing an `Options` by hand. Fields may be added in later releases, so outside this crate
+/// an `Options` is built by changing the fields of `Options::default()`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The bytes that separate one entry from the next. Must not be empty.
+    pub delim: Vec<u8>,
+    /// If set, each match of this regular expression starts a new entry, so that an entry can span
+    /// several lines. `delim` is then only stripped from the end of each entry. Use `(?m)^` to
+    /// anchor matches to the start of a line.
+    pub record_start: Option<Regex>,
+    /// Whether to append the delimiter to a final entry that lacks one. If `false`, such an entry
+    /// is written exactly as it appeared in the input.
+    pub add_terminator: bool,
+    /// Whether to skip writing entries entirely, for callers that are only interested in the
+    /// returned [`Stats`](struct.Stats.html).
+    pub count_only: bool,
+    /// If set, each unique entry is written once along with the number of times it occurred.
+    pub occurrences: Option<CountPosition>,
+    /// Which unique entries are written, based on how many times they occur.
+    pub filter: Filter,
+    /// Which occurrence of each entry is written.
+    pub keep: Keep,
+    /// Which unique entries are written, based on how many of the inputs they occur in.
+    pub set_operation: SetOperation,
+    /// If set, entries are compared by the selected fields rather than in their entirety. The
+    /// whole entry is still written.
+    pub key_fields: Option<KeyFields>,
+    /// If set, entries are compared by a range of bytes or characters only. This is applied after
+    /// `key_fields`.
+    pub key_range: Option<KeyRange>,
+    /// If set, entries are compared by a capture group of a regular expression. This is applied
+    /// after `key_fields` and `key_range`.
+    pub key_regex: Option<KeyRegex>,
+    /// What happens to entries that `key_regex` does not match.
+    pub unmatched: Unmatched,
+    /// Rules for ignoring differences in whitespace when comparing entries.
+    pub whitespace: Whitespace,
+    /// If set, keys are case folded so that entries differing only in case compare equal.
+    pub case_folding: Option<CaseFolding>,
+    /// If set, keys are converted to a Unicode normalization form before they are compared.
+    pub normalization: Option<Normalization>,
+    /// Whether a stream remembers a 128-bit fingerprint of each key rather than the key itself.
+    /// This uses far less memory for long keys, at a negligible risk of discarding an entry whose
+    /// fingerprint collides with an earlier one. Modes that hold every unique entry until the end
+    /// of the input, such as counting occurrences, keeping the last occurrence, `max_memory` and
+    /// `record_start`, are unaffected.
+    pub hash_only: bool,
+    /// If set, seen keys are remembered in a Bloom filter of fixed size instead of a set, so that
+    /// an unbounded input can be deduplicated in fixed memory. Some unique entries are wrongly
+    /// discarded as duplicates, at the configured rate. Modes that count occurrences or that hold
+    /// entries until the end of a stream are unaffected.
+    pub bloom_filter: Option<BloomConfig>,
+    /// If set, at most this many keys are remembered, and the least recently seen key is
+    /// forgotten to make room for a new one. This caps memory use, but an entry whose key has been
+    /// forgotten is written again. Modes that count occurrences or that hold entries until the end
+    /// of a stream are unaffected.
+    pub max_entries: Option<usize>,
+    /// If set, a stream only suppresses entries seen within this window, and forgets entries once
+    /// it passes so that memory use stays bounded. Every entry is written as soon as it is read,
+    /// so running returns an error if any option that needs the whole input or remembers keys in
+    /// another way, such as counting occurrences, `record_start` or `max_entries`, is also set.
+    pub window: Option<Window>,
+    /// If set, the approximate number of bytes of entries a stream is allowed to hold in memory.
+    /// Once exceeded, entries are partitioned into temporary files and deduplicated one partition
+    /// at a time. The output is the same either way. Not applied when `record_start` is set, since
+    /// the whole stream is then read into memory to find where entries start.
+    pub max_memory: Option<usize>,
+    /// The number of threads a buffer is deduplicated on, or 0 for one per CPU. Only keeping the
+    /// first occurrence of each entry without counting occurrences runs on several threads, and
+    /// only when entries are split on a delimiter that can't overlap itself. The output is the
+    /// same either way.
+    pub threads: usize,
+    /// The directory temporary files are created in when `max_memory` is exceeded. Defaults to
+    /// the system's temporary directory.
+    pub spill_dir: Option<PathBuf>,
+    /// Keeps `Options` from being built outside this crate, so that fields can be added.
+    pub(crate) _private: (),
+}
+
+impl Options {
+    /// Whether every occurrence of an entry must be counted before any entry can be written.
+    pub fn needs_counts(&self) -> bool {
+        self.occurrences.is_some()
+            || self.filter != Filter::All
+            || self.set_operation != SetOperation::Union
+    }
+
+    /// Returns the key that an entry, without its delimiter, is compared by, or `None` if the entry
+    /// has no key.
+    pub fn key<'a>(&self, body: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        let key = match self.key_fields {
+            Some(ref fields) => fields.select(body),
+            None => Cow::Borrowed(body),
+        };
+        let key = match self.key_range {
+            Some(ref range) => range.narrow(key),
+            None => key,
+        };
+        let key = match self.key_regex {
+            Some(ref regex) => regex.capture(key)?,
+            None => key,
+        };
+        Some(self.transform(key))
+    }
+
+    /// Applies the configured whitespace rules, case folding and Unicode normalization to `key`.
+    /// Normalization is applied again after folding, since folding can produce unnormalized text.
+    fn transform<'a>(&self, key: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        let key = self.whitespace.apply(key);
+        match (self.case_folding, self.normalization) {
+            (None, None) => key,
+            (Some(folding), None) => folding.fold(key),
+            (None, Some(form)) => form.normalize(key),
+            (Some(folding), Some(form)) => form.normalize(folding.fold(form.normalize(key))),
+        }
+    }
+}
+
+/// Where the number of occurrences is written relative to an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountPosition {
+    /// Before the entry, right-aligned and followed by a space, in the style of `uniq -c`.
+    Prefix,
+    /// After the entry and before its delimiter, separated from the entry by a tab.
+    Suffix,
+}
+
+/// Selects which occurrence of an entry is written, and therefore where it appears in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// Each entry is written at the position of its first occurrence.
+    First,
+    /// Each entry is written at the position of its last occurrence.
+    Last,
+}
+
+/// Selects which unique entries are written, based on how many times they occur.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Every unique entry is written.
+    All,
+    /// Only entries that occur more than once are written, in the style of `uniq -d`.
+    Repeated,
+    /// Only entries that occur exactly once are written, in the style of `uniq -u`.
+    Unrepeated,
+}
+
+impl Filter {
+    /// Whether an entry that occurs `count` times passes the filter.
+    pub fn matches(&self, count: u64) -> bool {
+        match *self {
+            Filter::All => true,
+            Filter::Repeated => count > 1,
+            Filter::Unrepeated => count == 1,
+        }
+    }
+}
+
+/// Selects which unique entries are written when deduplicating several inputs, based on how many
+/// of them each entry occurs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    /// Every unique entry is written.
+    Union,
+    /// Only entries that occur in every input are written.
+    Intersection,
+    /// Only entries that occur in exactly one input are written.
+    SymmetricDifference,
+}
+
+impl SetOperation {
+    /// Whether an entry that occurs in `inputs` of `total_inputs` inputs is written.
+    pub fn matches(&self, inputs: usize, total_inputs: usize) -> bool {
+        match *self {
+            SetOperation::Union => true,
+            SetOperation::Intersection => inputs == total_inputs,
+            SetOperation::SymmetricDifference => inputs == 1,
+        }
+    }
+}
+
+/// How long an entry is remembered for after it is last seen, when deduplicating within a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// An entry is suppressed if it was seen within this many of the previous entries. Every entry
+    /// read counts, including those that are excluded or have no key.
+    Entries(u64),
+    /// An entry is suppressed if it was seen within this long before it is read.
+    Time(Duration),
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            delim: vec![b'\n'],
+            record_start: None,
+            add_terminator: false,
+            count_only: false,
+            occurrences: None,
+            filter: Filter::All,
+            keep: Keep::First,
+            set_operation: SetOperation::Union,
+            key_fields: None,
+            key_range: None,
+            key_regex: None,
+            unmatched: Unmatched::Pass,
+            whitespace: Whitespace::default(),
+            case_folding: None,
+            normalization: None,
+            hash_only: false,
+            bloom_filter: None,
+            max_entries: None,
+            window: None,
+            max_memory: None,
+            threads: 1,
+            spill_dir: None,
+            _private: (),
+        }
+    }
+}
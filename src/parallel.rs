@@ -0,0 +1,189 @@
+use fxhash::FxHasher;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use error::DedupError;
+use key::Unmatched;
+use options::Options;
+use records::{Record, Records, Splitter};
+use set::{Map, Set};
+use stats::Stats;
+
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::sync::Mutex;
+
+/// The smallest chunk worth handing to a thread of its own.
+const MIN_CHUNK_LEN: usize = 1 << 20;
+/// How many chunks each thread is given on average, so that threads that finish early can take
+/// work from those that don't.
+const CHUNKS_PER_THREAD: usize = 4;
+/// How many shards of the set of seen keys there are for each thread, to keep threads from
+/// waiting on each other to insert keys.
+const SHARDS_PER_THREAD: usize = 4;
+
+/// Part of the set of seen keys, mapping each key to the earliest chunk it is found in and its
+/// index among that chunk's `records`.
+type Shard<'a> = Mutex<Map<Cow<'a, [u8]>, (usize, usize)>>;
+
+/// The entries of a chunk that are the first in the chunk with their key.
+struct Chunk<'a> {
+    records: Vec<Record<'a>>,
+    /// Whether each of `records` is the first in the whole input with its key. Entries without
+    /// a key are always written.
+    written: Vec<bool>,
+    stats: Stats,
+}
+
+/// Runs `BufferDeduper` on `threads` threads, or one per CPU if `threads` is 0.
+///
+/// The buffers are split into chunks between entries, which are processed in two passes. First,
+/// each chunk is deduplicated on its own, and the key of each entry that remains is recorded in
+/// a sharded set along with the earliest chunk it is found in. An entry is then written only if
+/// its chunk is the earliest with its key, which leaves the first occurrence of each entry in
+/// input order, exactly as a single thread would.
+pub fn run<'a, W: io::Write>(
+    buffers: &[&'a [u8]],
+    out: &mut W,
+    opts: &Options,
+    excluded: &Set<Cow<'a, [u8]>>,
+    threads: usize,
+) -> Result<Option<Stats>, DedupError> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let threads = pool.current_num_threads();
+    let len: usize = buffers.iter().map(|buffer| buffer.len()).sum();
+    let chunk_len = (len / (threads * CHUNKS_PER_THREAD)).max(MIN_CHUNK_LEN);
+
+    let splitter = Splitter::new(opts);
+    let chunks = match splitter.split_chunks(buffers, chunk_len) {
+        Some(chunks) => chunks,
+        None => return Ok(None),
+    };
+    let shards: Vec<Shard<'a>> = (0..threads * SHARDS_PER_THREAD)
+        .map(|_| Mutex::new(Map::default()))
+        .collect();
+
+    let mut chunks: Vec<Chunk<'a>> = pool.install(|| {
+        chunks
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, records)| dedup_chunk(i, records, opts, excluded, &shards))
+            .collect()
+    });
+    for shard in shards {
+        let firsts = shard.into_inner().unwrap_or_else(|e| e.into_inner());
+        for (chunk, record) in firsts.values().cloned() {
+            chunks[chunk].written[record] = true;
+        }
+    }
+
+    let mut stats = Stats::default();
+    for chunk in chunks {
+        stats.total += chunk.stats.total;
+        stats.dropped += chunk.stats.dropped;
+        stats.excluded += chunk.stats.excluded;
+        for (record, written) in chunk.records.iter().zip(chunk.written) {
+            if written {
+                stats.unique += 1;
+                if !opts.count_only {
+                    record.write_to(out, opts)?;
+                }
+            }
+        }
+    }
+    Ok(Some(stats))
+}
+
+/// Deduplicates the entries of chunk `index` on their own, then records the key of each that
+/// remains in `shards`, unless an earlier chunk has already recorded it.
+fn dedup_chunk<'a>(
+    index: usize,
+    records: Records<'a, '_>,
+    opts: &Options,
+    excluded: &Set<Cow<'a, [u8]>>,
+    shards: &[Shard<'a>],
+) -> Chunk<'a> {
+    let mut chunk = Chunk {
+        records: Vec::new(),
+        written: Vec::new(),
+        stats: Stats::default(),
+    };
+    let mut firsts = Map::default();
+    for record in records {
+        chunk.stats.total += 1;
+        match opts.key(record.body) {
+            Some(ref key) if excluded.contains(&key[..]) => chunk.stats.excluded += 1,
+            Some(key) => {
+                if let Entry::Vacant(first) = firsts.entry(key) {
+                    first.insert(chunk.records.len());
+                    chunk.records.push(record);
+                    chunk.written.push(false);
+                }
+            }
+            None if opts.unmatched == Unmatched::Pass => {
+                chunk.records.push(record);
+                chunk.written.push(true);
+            }
+            None => chunk.stats.dropped += 1,
+        }
+    }
+
+    let mut by_shard: Vec<Vec<_>> = shards.iter().map(|_| Vec::new()).collect();
+    for (key, record) in firsts {
+        let mut hasher = FxHasher::default();
+        key.hash(&mut hasher);
+        by_shard[hasher.finish() as usize % shards.len()].push((key, record));
+    }
+    for (shard, keys) in shards.iter().zip(by_shard) {
+        if keys.is_empty() {
+            continue;
+        }
+        let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+        for (key, record) in keys {
+            let first = shard.entry(key).or_insert((index, record));
+            if first.0 > index {
+                *first = (index, record);
+            }
+        }
+    }
+    chunk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::BufferDeduper;
+    use options::Keep;
+
+    #[test]
+    fn parallel_matches_single_thread() {
+        // Large enough to be split into several chunks.
+        let mut input = Vec::new();
+        for i in 0..400_000u32 {
+            input.extend_from_slice(format!("{}\n", (i * 7919) % 250_000).as_bytes());
+        }
+        input.extend_from_slice(b"unterminated");
+
+        let mut expected = Vec::new();
+        let expected_stats = BufferDeduper::new(&input, &mut expected, Options::default())
+            .run()
+            .unwrap();
+        let opts = Options {
+            threads: 4,
+            keep: Keep::First,
+            ..Options::default()
+        };
+        let mut output = Vec::new();
+        let stats = run(&[&input[..]], &mut output, &opts, &Set::default(), 4)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stats, expected_stats);
+        assert_eq!(stats.unique, 250_001);
+        assert!(output == expected);
+    }
+}
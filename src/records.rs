@@ -0,0 +1,345 @@
+use fastchr::{fastmem, fastrmem};
+use regex::bytes::Regex;
+
+use options::{CountPosition, Options};
+
+use std::io;
+
+/// A single entry of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    /// The entry as it appeared in the input, including its delimiter if it had one.
+    pub raw: &'a [u8],
+    /// The entry without its delimiter. This is what entries are compared by.
+    pub body: &'a [u8],
+    /// Whether the entry comes from an input that is followed by another, so that if it lacks a
+    /// delimiter one is written regardless of `add_terminator`, keeping it apart from the next.
+    pub followed: bool,
+}
+
+impl<'a> Record<'a> {
+    pub fn from_raw(raw: &'a [u8], delim: &[u8]) -> Self {
+        let body = if raw.ends_with(delim) {
+            &raw[..raw.len() - delim.len()]
+        } else {
+            raw
+        };
+        Record {
+            raw,
+            body,
+            followed: false,
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.raw.len() != self.body.len()
+    }
+
+    /// Whether the delimiter is appended to the entry when it lacks one.
+    fn appends_terminator(&self, opts: &Options) -> bool {
+        opts.add_terminator || self.followed
+    }
+
+    /// Writes the entry to `out`, appending the delimiter to an unterminated entry if requested.
+    pub fn write_to<W: io::Write>(&self, out: &mut W, opts: &Options) -> io::Result<()> {
+        out.write_all(self.raw)?;
+        if self.appends_terminator(opts) && !self.is_terminated() {
+            out.write_all(&opts.delim)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the entry to `out`, along with its number of occurrences if they were requested.
+    pub fn write_counted_to<W: io::Write>(
+        &self,
+        out: &mut W,
+        count: u64,
+        opts: &Options,
+    ) -> io::Result<()> {
+        match opts.occurrences {
+            None => self.write_to(out, opts),
+            Some(CountPosition::Prefix) => {
+                write!(out, "{:7} ", count)?;
+                self.write_to(out, opts)
+            }
+            Some(CountPosition::Suffix) => {
+                out.write_all(self.body)?;
+                write!(out, "\t{}", count)?;
+                if self.appends_terminator(opts) || self.is_terminated() {
+                    out.write_all(&opts.delim)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How a buffer is split into entries, copied out of the `Options` so that it can be borrowed
+/// independently of them.
+#[derive(Debug, Clone)]
+pub struct Splitter {
+    delim: Vec<u8>,
+    start: Option<Regex>,
+}
+
+impl Splitter {
+    pub fn new(opts: &Options) -> Self {
+        Splitter {
+            delim: opts.delim.clone(),
+            start: opts.record_start.clone(),
+        }
+    }
+
+    pub fn split<'a, 'd>(&'d self, buffer: &'a [u8]) -> Records<'a, 'd> {
+        Records {
+            delim: &self.delim,
+            start: self.start.as_ref(),
+            buffer,
+            followed: false,
+            backward: None,
+        }
+    }
+
+    /// Splits each of `buffers` in turn, as though they were a single input in which every buffer
+    /// ends with a delimiter.
+    pub fn split_all<'a, 'd>(
+        &'d self,
+        buffers: &'d [&'a [u8]],
+    ) -> impl DoubleEndedIterator<Item = Record<'a>> + 'd {
+        let last = buffers.len().saturating_sub(1);
+        buffers.iter().enumerate().flat_map(move |(i, buffer)| {
+            let mut records = self.split(buffer);
+            records.followed = i < last;
+            records
+        })
+    }
+
+    /// Splits each of `buffers` into chunks of about `chunk_len` bytes, cut only between entries,
+    /// so that the chunks can be split into entries independently and yield the same entries as
+    /// `split_all`.
+    ///
+    /// Returns `None` if entries start at matches of a regex, or if the delimiter can overlap
+    /// itself, since then where an entry starts depends on everything before it.
+    pub fn split_chunks<'a, 'd>(
+        &'d self,
+        buffers: &[&'a [u8]],
+        chunk_len: usize,
+    ) -> Option<Vec<Records<'a, 'd>>> {
+        if self.start.is_some() || overlaps_itself(&self.delim) {
+            return None;
+        }
+        let last = buffers.len().saturating_sub(1);
+        let mut chunks = Vec::new();
+        for (i, &buffer) in buffers.iter().enumerate() {
+            let mut rest = buffer;
+            while !rest.is_empty() {
+                let end = match rest.get(chunk_len..) {
+                    Some(tail) => fastmem(&self.delim, tail)
+                        .map_or(rest.len(), |u| chunk_len + u + self.delim.len()),
+                    None => rest.len(),
+                };
+                let (chunk, tail) = rest.split_at(end);
+                rest = tail;
+                let mut records = self.split(chunk);
+                records.followed = i < last;
+                chunks.push(records);
+            }
+        }
+        Some(chunks)
+    }
+}
+
+/// An iterator over the entries of a buffer.
+///
+/// The final entry is yielded even if it lacks a delimiter.
+#[derive(Debug, Clone)]
+pub struct Records<'a, 'd> {
+    delim: &'d [u8],
+    /// If set, each match starts a new entry and the delimiter is only stripped from entry ends.
+    start: Option<&'d Regex>,
+    buffer: &'a [u8],
+    /// Whether the buffer is followed by another input.
+    followed: bool,
+    /// For a delimiter that can overlap itself, such as `\n\n`, or for entries that begin at
+    /// matches of a regex, where an entry starts can't be found by searching backwards. The
+    /// remaining entries are found by a forward scan instead, the first time one is needed from
+    /// the back.
+    backward: Option<Vec<Record<'a>>>,
+}
+
+impl<'a, 'd> Records<'a, 'd> {
+    fn record(&self, raw: &'a [u8]) -> Record<'a> {
+        let mut record = Record::from_raw(raw, self.delim);
+        record.followed = self.followed;
+        record
+    }
+}
+
+/// Whether a suffix of `delim` is also a prefix of it, so that two matches can overlap.
+fn overlaps_itself(delim: &[u8]) -> bool {
+    (1..delim.len()).any(|k| delim[k..] == delim[..delim.len() - k])
+}
+
+impl<'a, 'd> Iterator for Records<'a, 'd> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        let end = match self.start {
+            // Searching from the second byte lets anchors such as `(?m)^` see the preceding byte.
+            Some(start) => start
+                .find_at(self.buffer, 1)
+                .map_or(self.buffer.len(), |m| m.start()),
+            None => fastmem(self.delim, self.buffer)
+                .map_or(self.buffer.len(), |u| u + self.delim.len()),
+        };
+        let (raw, rest) = self.buffer.split_at(end);
+        self.buffer = rest;
+        Some(self.record(raw))
+    }
+}
+
+impl<'a, 'd> DoubleEndedIterator for Records<'a, 'd> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        if self.start.is_some() || overlaps_itself(self.delim) {
+            if self.backward.is_none() {
+                self.backward = Some(self.clone().collect());
+            }
+            let record = self.backward.as_mut().and_then(Vec::pop)?;
+            self.buffer = &self.buffer[..self.buffer.len() - record.raw.len()];
+            return Some(record);
+        }
+
+        let end = if self.buffer.ends_with(self.delim) {
+            self.buffer.len() - self.delim.len()
+        } else {
+            self.buffer.len()
+        };
+
+        let start = fastrmem(self.delim, &self.buffer[..end]).map_or(0, |u| u + self.delim.len());
+        let (rest, raw) = self.buffer.split_at(start);
+        self.buffer = rest;
+        Some(self.record(raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records<'a>(delim: &'a [u8], buffer: &'a [u8]) -> Records<'a, 'a> {
+        Records {
+            delim,
+            start: None,
+            buffer,
+            followed: false,
+            backward: None,
+        }
+    }
+
+    #[test]
+    fn unterminated_final_record() {
+        let mut records = records(b"\n", b"spam\nham");
+
+        let spam = records.next().unwrap();
+        assert_eq!(spam.body, b"spam");
+        assert!(spam.is_terminated());
+
+        let ham = records.next().unwrap();
+        assert_eq!(ham.raw, b"ham");
+        assert!(!ham.is_terminated());
+
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn reversed_records() {
+        let forward: Vec<_> = records(b"\n", b"spam\n\nham\neggs").collect();
+        let mut backward: Vec<_> = records(b"\n", b"spam\n\nham\neggs").rev().collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+
+        let forward: Vec<_> = records(b"\n", b"\nspam\n").collect();
+        let mut backward: Vec<_> = records(b"\n", b"\nspam\n").rev().collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn multi_byte_delimiter() {
+        let records: Vec<_> = records(b"\r\n", b"spam\r\nham\neggs\r\n\r")
+            .map(|record| record.body)
+            .collect();
+        assert_eq!(records, vec![&b"spam"[..], b"ham\neggs", b"\r"]);
+
+        let record = Record::from_raw(b"spam\r\n", b"\r\n");
+        assert_eq!(record.body, b"spam");
+        assert!(record.is_terminated());
+    }
+
+    #[test]
+    fn reversed_overlapping_delimiter() {
+        for input in &[&b"spam\n\n\nham\n\neggs"[..], b"\n\n\n\nspam\n\n\n", b"\n\n\n"] {
+            let forward: Vec<_> = records(b"\n\n", input).collect();
+            let mut backward: Vec<_> = records(b"\n\n", input).rev().collect();
+            backward.reverse();
+            assert_eq!(forward, backward);
+        }
+
+        let mut records = records(b"\n\n", b"spam\n\n\nham\n\neggs\n\n");
+        assert_eq!(records.next().unwrap().body, b"spam");
+        assert_eq!(records.next_back().unwrap().body, b"eggs");
+        assert_eq!(records.next_back().unwrap().body, b"\nham");
+        assert!(records.next().is_none());
+        assert!(records.next_back().is_none());
+    }
+
+    #[test]
+    fn regex_record_starts() {
+        let splitter = Splitter {
+            delim: b"\n".to_vec(),
+            start: Some(Regex::new(r"(?m)^\d{4}-").unwrap()),
+        };
+        let input = b"preamble\n2018-01 spam\n  at ham\n2018-02 eggs\n2018-03 spam\n  at ham";
+        let bodies: Vec<_> = splitter.split(input).map(|record| record.body).collect();
+        assert_eq!(
+            bodies,
+            vec![
+                &b"preamble"[..],
+                b"2018-01 spam\n  at ham",
+                b"2018-02 eggs",
+                b"2018-03 spam\n  at ham",
+            ]
+        );
+
+        let mut backward: Vec<_> = splitter.split(input).rev().collect();
+        backward.reverse();
+        assert_eq!(splitter.split(input).collect::<Vec<_>>(), backward);
+    }
+
+    #[test]
+    fn split_chunks() {
+        let splitter = Splitter {
+            delim: b"\r\n".to_vec(),
+            start: None,
+        };
+        let buffers: [&[u8]; 2] = [b"spam\r\nham\neggs\r\n\r", b"ham\r\nspam\r\neggs"];
+        let expected: Vec<_> = splitter.split_all(&buffers).collect();
+        for chunk_len in 0..20 {
+            let chunks = splitter.split_chunks(&buffers, chunk_len).unwrap();
+            assert_eq!(chunks.into_iter().flatten().collect::<Vec<_>>(), expected);
+        }
+
+        let overlapping = Splitter {
+            delim: b"\n\n".to_vec(),
+            start: None,
+        };
+        assert!(overlapping.split_chunks(&buffers, 4).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use std::io;
+
+/// Where each duplicated entry of a set of inputs occurs, as produced by
+/// [`BufferDeduper::report`](struct.BufferDeduper.html#method.report).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report<'a> {
+    /// Every entry that occurs more than once, in the order they first occur.
+    pub duplicates: Vec<Duplicate<'a>>,
+}
+
+/// An entry that occurs more than once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate<'a> {
+    /// The first occurrence of the entry, without its delimiter.
+    pub entry: &'a [u8],
+    /// Every occurrence of the entry, including the first, in input order.
+    pub occurrences: Vec<Occurrence>,
+}
+
+/// A single occurrence of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Occurrence {
+    /// The index of the input the entry occurs in.
+    pub input: usize,
+    /// The 1-based number of the entry within its input.
+    pub record: u64,
+    /// The offset in bytes of the start of the entry within its input.
+    pub offset: usize,
+}
+
+impl<'a> Report<'a> {
+    /// Writes the report as plain text: each duplicated entry on a line of its own, followed by
+    /// one tab-indented `name:record` line per occurrence.
+    ///
+    /// `names` holds the name of each input, such as its path. Inputs without a name are
+    /// identified by their index.
+    pub fn write_text<W, S>(&self, out: &mut W, names: &[S]) -> io::Result<()>
+    where
+        W: io::Write,
+        S: AsRef<str>,
+    {
+        for duplicate in &self.duplicates {
+            out.write_all(duplicate.entry)?;
+            out.write_all(b"\n")?;
+            for occurrence in &duplicate.occurrences {
+                out.write_all(b"\t")?;
+                write_name(out, names, occurrence.input)?;
+                writeln!(out, ":{}", occurrence.record)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the report as a JSON array with one object per duplicated entry, such as
+    /// `{"entry":"ham","occurrences":[{"input":"a.txt","record":2,"offset":5}, ...]}`.
+    ///
+    /// Entries that are not valid UTF-8 are written lossily. `names` is as for `write_text`.
+    pub fn write_json<W, S>(&self, out: &mut W, names: &[S]) -> io::Result<()>
+    where
+        W: io::Write,
+        S: AsRef<str>,
+    {
+        out.write_all(b"[")?;
+        for (i, duplicate) in self.duplicates.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            out.write_all(b"\n{\"entry\":")?;
+            write_json_string(out, &String::from_utf8_lossy(duplicate.entry))?;
+            out.write_all(b",\"occurrences\":[")?;
+            for (j, occurrence) in duplicate.occurrences.iter().enumerate() {
+                if j > 0 {
+                    out.write_all(b",")?;
+                }
+                out.write_all(b"{\"input\":")?;
+                match names.get(occurrence.input) {
+                    Some(name) => write_json_string(out, name.as_ref())?,
+                    None => write!(out, "{}", occurrence.input)?,
+                }
+                write!(
+                    out,
+                    ",\"record\":{},\"offset\":{}}}",
+                    occurrence.record, occurrence.offset
+                )?;
+            }
+            out.write_all(b"]}")?;
+        }
+        out.write_all(b"\n]\n")
+    }
+}
+
+fn write_name<W, S>(out: &mut W, names: &[S], input: usize) -> io::Result<()>
+where
+    W: io::Write,
+    S: AsRef<str>,
+{
+    match names.get(input) {
+        Some(name) => out.write_all(name.as_ref().as_bytes()),
+        None => write!(out, "{}", input),
+    }
+}
+
+fn write_json_string<W: io::Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str;
+
+    fn report() -> Report<'static> {
+        Report {
+            duplicates: vec![
+                Duplicate {
+                    entry: b"ham",
+                    occurrences: vec![
+                        Occurrence {
+                            input: 0,
+                            record: 2,
+                            offset: 5,
+                        },
+                        Occurrence {
+                            input: 1,
+                            record: 1,
+                            offset: 0,
+                        },
+                    ],
+                },
+                Duplicate {
+                    entry: b"\"spam\"\teggs",
+                    occurrences: vec![
+                        Occurrence {
+                            input: 0,
+                            record: 3,
+                            offset: 9,
+                        },
+                        Occurrence {
+                            input: 2,
+                            record: 7,
+                            offset: 40,
+                        },
+                    ],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn text_report() {
+        let mut output = Vec::new();
+        report().write_text(&mut output, &["a.txt", "-"]).unwrap();
+        let expected = "ham\n\ta.txt:2\n\t-:1\n\"spam\"\teggs\n\ta.txt:3\n\t2:7\n";
+        assert_eq!(expected, str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn json_report() {
+        let mut output = Vec::new();
+        report().write_json(&mut output, &["a.txt", "-"]).unwrap();
+        let expected = "[\n\
+            {\"entry\":\"ham\",\"occurrences\":[\
+            {\"input\":\"a.txt\",\"record\":2,\"offset\":5},\
+            {\"input\":\"-\",\"record\":1,\"offset\":0}]},\n\
+            {\"entry\":\"\\\"spam\\\"\\teggs\",\"occurrences\":[\
+            {\"input\":\"a.txt\",\"record\":3,\"offset\":9},\
+            {\"input\":2,\"record\":7,\"offset\":40}]}\n]\n";
+        assert_eq!(expected, str::from_utf8(&output).unwrap());
+
+        let mut output = Vec::new();
+        Report::default().write_json(&mut output, &["a.txt"]).unwrap();
+        assert_eq!("[\n]\n", str::from_utf8(&output).unwrap());
+    }
+}
@@ -1,6 +1,614 @@
 use fxhash::FxHasher;
+use siphasher::sip128::{Hasher128, SipHasher13};
 
-use std::collections::HashSet;
-use std::hash::BuildHasherDefault;
+use options::Window;
 
-pub type Set<T> = HashSet<T, BuildHasherDefault<FxHasher>>;
\ No newline at end of file
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// The hash set used to remember entries that have already been seen.
+pub type Set<T> = HashSet<T, BuildHasherDefault<FxHasher>>;
+
+/// The hash map used to associate seen entries with data such as their number of occurrences.
+pub type Map<K, V> = HashMap<K, V, BuildHasherDefault<FxHasher>>;
+
+/// A collection of the keys of entries that have already been seen.
+///
+/// `Set` is the exact implementation. Others trade exactness for memory, and can be used by the
+/// deduplication engines in its place.
+pub trait KeySet<K> {
+    /// Whether `key` has been inserted.
+    fn contains(&self, key: &[u8]) -> bool;
+
+    /// Inserts `key`, returning `true` if it was not already present.
+    fn insert(&mut self, key: K) -> bool;
+
+    /// Whether `key` has been inserted, marking it as recently seen for sets that forget the
+    /// least recently seen keys.
+    fn touch(&mut self, key: &[u8]) -> bool {
+        self.contains(key)
+    }
+
+    /// Reserves room for at least `additional` more keys, if the set grows as keys are inserted.
+    fn reserve(&mut self, _additional: usize) {}
+
+    /// The approximate number of bytes saved by not storing `key` itself, for sets that store
+    /// something smaller in its place.
+    fn bytes_saved(&self, _key: &[u8]) -> usize {
+        0
+    }
+
+    /// The number of keys the set has forgotten to make room for others.
+    fn evictions(&self) -> u64 {
+        0
+    }
+}
+
+impl<K> KeySet<K> for Set<K>
+where
+    K: Borrow<[u8]> + Hash + Eq,
+{
+    fn contains(&self, key: &[u8]) -> bool {
+        HashSet::contains(self, key)
+    }
+
+    fn insert(&mut self, key: K) -> bool {
+        HashSet::insert(self, key)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        HashSet::reserve(self, additional)
+    }
+}
+
+/// A `KeySet` that stores a 128-bit fingerprint of each key instead of the key itself, so that
+/// each key takes 16 bytes however long it is.
+///
+/// Distinct keys whose fingerprints collide are treated as duplicates. See
+/// [`collision_probability`](#method.collision_probability) for how unlikely that is.
+#[derive(Debug, Clone, Default)]
+pub struct FingerprintSet {
+    fingerprints: Set<u128>,
+}
+
+impl FingerprintSet {
+    /// Creates an empty `FingerprintSet`.
+    pub fn new() -> Self {
+        FingerprintSet::default()
+    }
+
+    /// Creates an empty `FingerprintSet` with room for at least `capacity` keys.
+    pub fn with_capacity(capacity: usize) -> Self {
+        FingerprintSet {
+            fingerprints: Set::with_capacity_and_hasher(capacity, Default::default()),
+        }
+    }
+
+    /// Returns the 128-bit fingerprint of `key`.
+    pub fn fingerprint(key: &[u8]) -> u128 {
+        let mut hasher = SipHasher13::new();
+        hasher.write(key);
+        let hash = hasher.finish128();
+        u128::from(hash.h1) << 64 | u128::from(hash.h2)
+    }
+
+    /// Returns the probability that any two of `keys` distinct keys share a fingerprint, and so
+    /// that an entry is wrongly discarded as a duplicate.
+    pub fn collision_probability(keys: u64) -> f64 {
+        let keys = keys as f64;
+        let pairs = keys * (keys - 1.0) / 2.0;
+        -(-pairs / 2f64.powi(128)).exp_m1()
+    }
+
+    /// Whether the fingerprint of `key` is in the set.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.fingerprints.contains(&FingerprintSet::fingerprint(key))
+    }
+
+    /// Adds the fingerprint of `key` to the set, returning `true` if it was not already present.
+    pub fn insert(&mut self, key: &[u8]) -> bool {
+        self.fingerprints.insert(FingerprintSet::fingerprint(key))
+    }
+
+    /// Returns an iterator over the fingerprints in the set, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = u128> + '_ {
+        self.fingerprints.iter().cloned()
+    }
+
+    /// The number of fingerprints in the set.
+    pub fn len(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty()
+    }
+}
+
+impl Extend<u128> for FingerprintSet {
+    fn extend<I: IntoIterator<Item = u128>>(&mut self, fingerprints: I) {
+        self.fingerprints.extend(fingerprints)
+    }
+}
+
+impl<K: AsRef<[u8]>> KeySet<K> for FingerprintSet {
+    fn contains(&self, key: &[u8]) -> bool {
+        FingerprintSet::contains(self, key)
+    }
+
+    fn insert(&mut self, key: K) -> bool {
+        FingerprintSet::insert(self, key.as_ref())
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.fingerprints.reserve(additional)
+    }
+
+    fn bytes_saved(&self, key: &[u8]) -> usize {
+        key.len() + mem::size_of::<Vec<u8>>() - mem::size_of::<u128>()
+    }
+}
+
+/// The parameters a `BloomFilter` is sized by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomConfig {
+    /// The number of distinct keys the filter is expected to hold.
+    pub expected_keys: u64,
+    /// The probability that a key which has not been inserted is reported as present, once
+    /// `expected_keys` keys have been. Must be between 0 and 1, exclusive.
+    pub false_positive_rate: f64,
+}
+
+/// A `KeySet` of fixed size that never forgets a key, but may mistake a new key for one it has
+/// seen, at a rate set by its [`BloomConfig`](struct.BloomConfig.html).
+///
+/// The false positive rate rises beyond the configured one as more than the expected number of
+/// keys are inserted.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    /// The number of bits in use, which is at most `bits.len() * 64`.
+    len: u64,
+    hashes: u32,
+}
+
+impl BloomConfig {
+    /// The number of bytes a filter with this configuration uses.
+    pub fn size_in_bytes(&self) -> usize {
+        ((self.bits() + 63) / 64 * 8) as usize
+    }
+
+    /// The number of bits a filter with this configuration sets for each key.
+    pub fn hashes(&self) -> u32 {
+        let keys = self.expected_keys.max(1) as f64;
+        (self.bits() as f64 / keys * 2f64.ln()).round().max(1.0) as u32
+    }
+
+    /// The optimal number of bits for the expected keys and false positive rate.
+    fn bits(&self) -> u64 {
+        let keys = self.expected_keys.max(1) as f64;
+        let bits = -keys * self.false_positive_rate.ln() / (2f64.ln() * 2f64.ln());
+        bits.ceil().max(64.0) as u64
+    }
+}
+
+impl BloomFilter {
+    /// Creates an empty `BloomFilter` sized by `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config.false_positive_rate` is not between 0 and 1, exclusive.
+    pub fn new(config: BloomConfig) -> Self {
+        let rate = config.false_positive_rate;
+        assert!(rate > 0.0 && rate < 1.0, "false positive rate must be between 0 and 1");
+        BloomFilter {
+            bits: vec![0; config.size_in_bytes() / 8],
+            len: config.bits(),
+            hashes: config.hashes(),
+        }
+    }
+
+    /// Returns the index of each bit that `key` sets, by double hashing its fingerprint.
+    fn indices(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let fingerprint = FingerprintSet::fingerprint(key);
+        let (h1, h2) = ((fingerprint >> 64) as u64, fingerprint as u64);
+        let len = self.len;
+        (0..u64::from(self.hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % len)
+    }
+}
+
+impl<K: AsRef<[u8]>> KeySet<K> for BloomFilter {
+    fn contains(&self, key: &[u8]) -> bool {
+        self.indices(key)
+            .all(|i| self.bits[(i / 64) as usize] & (1 << (i % 64)) != 0)
+    }
+
+    fn insert(&mut self, key: K) -> bool {
+        let mut inserted = false;
+        for i in self.indices(key.as_ref()) {
+            let word = &mut self.bits[(i / 64) as usize];
+            inserted |= *word & (1 << (i % 64)) == 0;
+            *word |= 1 << (i % 64);
+        }
+        inserted
+    }
+}
+
+/// Keys in the order they were last seen, each with a value, kept in a doubly linked list
+/// threaded through a `Vec` so that a key can be moved to the front, or the least recently seen
+/// key removed, in constant time. Each key is stored once, however often it is seen.
+#[derive(Debug, Clone)]
+struct Recency<V> {
+    slots: Map<Rc<[u8]>, usize>,
+    nodes: Vec<Node<V>>,
+    /// Slots of removed nodes, reused before `nodes` grows.
+    free: Vec<usize>,
+    /// The most recently seen key.
+    head: usize,
+    /// The least recently seen key.
+    tail: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node<V> {
+    /// `None` once the node is removed, so that the key isn't kept alive by a free slot.
+    key: Option<Rc<[u8]>>,
+    value: V,
+    prev: usize,
+    next: usize,
+}
+
+/// Marks the end of the list in `Recency`.
+const NIL: usize = usize::max_value();
+
+impl<V: Copy> Recency<V> {
+    fn new() -> Self {
+        Recency {
+            slots: Map::default(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.slots.contains_key(key)
+    }
+
+    /// Moves `key` to the front and replaces its value, returning `false` if it is not present.
+    fn touch(&mut self, key: &[u8], value: V) -> bool {
+        match self.slots.get(key) {
+            Some(&slot) => {
+                self.unlink(slot);
+                self.nodes[slot].value = value;
+                self.link_front(slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds `key`, which must not already be present, at the front.
+    fn push_front(&mut self, key: &[u8], value: V) {
+        let key: Rc<[u8]> = Rc::from(key);
+        let node = Node {
+            key: Some(Rc::clone(&key)),
+            value,
+            prev: NIL,
+            next: NIL,
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.link_front(slot);
+        self.slots.insert(key, slot);
+    }
+
+    /// The value of the least recently seen key.
+    fn oldest(&self) -> Option<V> {
+        match self.tail {
+            NIL => None,
+            tail => Some(self.nodes[tail].value),
+        }
+    }
+
+    /// Removes the least recently seen key, if there is one.
+    fn pop_oldest(&mut self) {
+        let slot = self.tail;
+        if slot == NIL {
+            return;
+        }
+        self.unlink(slot);
+        if let Some(key) = self.nodes[slot].key.take() {
+            self.slots.remove(&key);
+        }
+        self.free.push(slot);
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = (self.nodes[slot].prev, self.nodes[slot].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+    }
+
+    fn link_front(&mut self, slot: usize) {
+        self.nodes[slot].prev = NIL;
+        self.nodes[slot].next = self.head;
+        match self.head {
+            NIL => self.tail = slot,
+            head => self.nodes[head].prev = slot,
+        }
+        self.head = slot;
+    }
+}
+
+/// A `KeySet` that holds at most a fixed number of keys, forgetting the least recently seen key
+/// to make room for a new one. Entries whose keys have been forgotten are no longer recognised as
+/// duplicates, so deduplication is best-effort beyond that many distinct keys.
+#[derive(Debug, Clone)]
+pub struct LruSet {
+    keys: Recency<()>,
+    capacity: usize,
+    evictions: u64,
+}
+
+impl LruSet {
+    /// Creates an empty `LruSet` that holds at most `capacity` keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be at least 1");
+        LruSet {
+            keys: Recency::new(),
+            capacity,
+            evictions: 0,
+        }
+    }
+
+    /// The number of keys in the set.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: AsRef<[u8]>> KeySet<K> for LruSet {
+    fn contains(&self, key: &[u8]) -> bool {
+        self.keys.contains(key)
+    }
+
+    fn insert(&mut self, key: K) -> bool {
+        if self.keys.touch(key.as_ref(), ()) {
+            return false;
+        }
+        if self.keys.len() == self.capacity {
+            self.keys.pop_oldest();
+            self.evictions += 1;
+        }
+        self.keys.push_front(key.as_ref(), ());
+        true
+    }
+
+    fn touch(&mut self, key: &[u8]) -> bool {
+        self.keys.touch(key, ())
+    }
+
+    fn evictions(&self) -> u64 {
+        self.evictions
+    }
+}
+
+/// A set of recently seen keys, which forgets each key once a [`Window`](enum.Window.html) has
+/// passed since it was last seen. Memory use is proportional to the number of distinct keys seen
+/// within the window.
+#[derive(Debug, Clone)]
+pub struct ExpiringSet {
+    window: Window,
+    last_seen: Recency<Sighting>,
+    entries: u64,
+}
+
+/// When a key was seen, both as a count of sightings and as a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sighting {
+    entry: u64,
+    at: Instant,
+}
+
+impl ExpiringSet {
+    /// Creates an empty `ExpiringSet` that remembers keys for `window`.
+    pub fn new(window: Window) -> Self {
+        ExpiringSet {
+            window,
+            last_seen: Recency::new(),
+            entries: 0,
+        }
+    }
+
+    /// Records a sighting of `key` at the current time, returning `true` if it had not been seen
+    /// within the window.
+    pub fn insert(&mut self, key: &[u8]) -> bool {
+        self.insert_at(key, Instant::now())
+    }
+
+    /// Records a sighting of `key` at `now`, returning `true` if it had not been seen within the
+    /// window. Sightings must be recorded in chronological order.
+    pub fn insert_at(&mut self, key: &[u8], now: Instant) -> bool {
+        self.entries += 1;
+        let sighting = Sighting {
+            entry: self.entries,
+            at: now,
+        };
+        self.expire(sighting);
+
+        if self.last_seen.touch(key, sighting) {
+            return false;
+        }
+        self.last_seen.push_front(key, sighting);
+        true
+    }
+
+    /// Counts an entry whose key is not recorded, such as an excluded entry or one without a key,
+    /// towards a `Window::Entries` window.
+    pub fn skip(&mut self) {
+        self.entries += 1;
+    }
+
+    /// The number of keys currently remembered.
+    pub fn len(&self) -> usize {
+        self.last_seen.len()
+    }
+
+    /// Whether no keys are currently remembered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets every key whose last sighting falls outside the window as of `now`.
+    fn expire(&mut self, now: Sighting) {
+        while let Some(oldest) = self.last_seen.oldest() {
+            let expired = match self.window {
+                Window::Entries(entries) => now.entry - oldest.entry > entries,
+                Window::Time(duration) => now.at.duration_since(oldest.at) > duration,
+            };
+            if !expired {
+                break;
+            }
+            self.last_seen.pop_oldest();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_set() {
+        let mut set = FingerprintSet::new();
+        assert!(set.insert(b"spam"));
+        assert!(set.insert(b"ham"));
+        assert!(!set.insert(b"spam"));
+        assert!(set.contains(b"ham"));
+        assert!(!set.contains(b"eggs"));
+        assert_eq!(set.len(), 2);
+
+        assert_ne!(FingerprintSet::fingerprint(b""), FingerprintSet::fingerprint(b"\0"));
+        assert_eq!(FingerprintSet::collision_probability(1), 0.0);
+        let p = FingerprintSet::collision_probability(1 << 32);
+        assert!(p > 1e-20 && p < 1e-19);
+    }
+
+    #[test]
+    fn bloom_filter() {
+        let config = BloomConfig {
+            expected_keys: 10_000,
+            false_positive_rate: 0.01,
+        };
+        assert_eq!(config.hashes(), 7);
+        assert_eq!(config.size_in_bytes(), 11_984);
+        let mut filter = BloomFilter::new(config);
+
+        // A new key can already be a false positive as the filter fills up.
+        let inserted = (0..10_000u32)
+            .filter(|i| filter.insert(i.to_string().into_bytes()))
+            .count();
+        assert!(inserted > 9_900, "{} inserted", inserted);
+        for i in 0..10_000u32 {
+            assert!(KeySet::<Vec<u8>>::contains(&filter, i.to_string().as_bytes()));
+            assert!(!filter.insert(i.to_string().into_bytes()));
+        }
+        let false_positives = (10_000..20_000u32)
+            .filter(|i| KeySet::<Vec<u8>>::contains(&filter, i.to_string().as_bytes()))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn expiring_set_entries() {
+        let mut set = ExpiringSet::new(Window::Entries(2));
+        let inserted: Vec<bool> = ["spam", "ham", "spam", "eggs", "eggs", "ham", "spam", "spam"]
+            .iter()
+            .map(|key| set.insert(key.as_bytes()))
+            .collect();
+        assert_eq!(inserted, vec![true, true, false, true, false, true, true, false]);
+        assert_eq!(set.len(), 2);
+
+        // Skipped entries count towards the window too.
+        set.skip();
+        assert!(!set.insert(b"spam"));
+        set.skip();
+        set.skip();
+        assert!(set.insert(b"spam"));
+    }
+
+    #[test]
+    fn expiring_set_time() {
+        use std::time::Duration;
+
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut set = ExpiringSet::new(Window::Time(Duration::from_secs(10)));
+        assert!(set.insert_at(b"spam", at(0)));
+        assert!(!set.insert_at(b"spam", at(8)));
+        assert!(set.insert_at(b"ham", at(9)));
+        // Seeing spam at 8 kept it for another 10 seconds.
+        assert!(!set.insert_at(b"spam", at(17)));
+        assert!(set.insert_at(b"ham", at(20)));
+        assert!(set.insert_at(b"spam", at(30)));
+        assert_eq!(set.len(), 2);
+
+        // Seeing a key again moves it rather than storing it again.
+        for secs in 31..1000 {
+            assert!(!set.insert_at(b"spam", at(secs)));
+        }
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.last_seen.nodes.len(), 2);
+    }
+
+    #[test]
+    fn lru_set() {
+        let mut set = LruSet::new(2);
+        assert!(set.insert(b"spam".to_vec()));
+        assert!(set.insert(b"ham".to_vec()));
+        assert!(!set.insert(b"spam".to_vec()));
+        // ham is now the least recently seen, so it makes way for eggs.
+        assert!(set.insert(b"eggs".to_vec()));
+        assert!(!KeySet::<Vec<u8>>::contains(&set, b"ham"));
+        assert!(KeySet::<Vec<u8>>::touch(&mut set, b"spam"));
+        assert!(set.insert(b"ham".to_vec()));
+        assert!(!KeySet::<Vec<u8>>::contains(&set, b"eggs"));
+        assert!(!set.insert(b"spam".to_vec()));
+        assert_eq!(set.len(), 2);
+        assert_eq!(KeySet::<Vec<u8>>::evictions(&set), 2);
+    }
+}
@@ -0,0 +1,422 @@
+use fxhash::FxHasher;
+
+use error::DedupError;
+use key::Unmatched;
+use options::{Keep, Options};
+use records::Record;
+use set::{Map, Set};
+use stats::Stats;
+use stream::{read_record, Entry};
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of partitions entries are spread across each time they are split.
+const FANOUT: usize = 32;
+/// How many times a partition that doesn't fit in memory is split again before it is processed
+/// regardless. Splitting can't help a partition made up of one huge entry, for instance.
+const MAX_DEPTH: u32 = 4;
+/// The most runs that are merged at once, to stay well within limits on open files.
+const MAX_MERGE: usize = 64;
+/// The approximate memory used by each entry held in memory, besides its contents.
+pub const ENTRY_OVERHEAD: usize = 64;
+
+/// Finishes a run of `UnsortedStreamDeduper` that has outgrown `opts.max_memory`.
+///
+/// The entries held so far, and every entry still to be read from `input`, are partitioned by
+/// the hash of their keys into temporary files. Each partition is then deduplicated on its own,
+/// producing a run of its unique entries in input order, and the runs are merged to write the
+/// unique entries in input order.
+pub fn run<R: io::BufRead, W: io::Write>(
+    input: &mut R,
+    out: &mut W,
+    opts: &Options,
+    excluded: &Set<Vec<u8>>,
+    entries: Map<Vec<u8>, Entry>,
+    passed: Vec<Entry>,
+    mut stats: Stats,
+) -> Result<Stats, DedupError> {
+    let mut spill = Spill {
+        dir: SpillDir::create(opts.spill_dir.as_ref())?,
+        opts,
+        runs: Vec::new(),
+        unique: 0,
+    };
+    let mut partitions = spill.create_partitions()?;
+    for (key, entry) in entries {
+        partitions.write(&Spilled::from_entry(key, entry), 0)?;
+    }
+
+    // Entries without a key are all unique, so they form a run of their own.
+    let (passed_path, mut passed_out) = spill.dir.create_file()?;
+    let mut passed_count = passed.len() as u64;
+    for entry in passed {
+        Spilled::from_entry(Vec::new(), entry).write_to(&mut passed_out)?;
+    }
+
+    loop {
+        let mut buf = Vec::new();
+        read_record(input, &opts.delim, &mut buf)?;
+        if buf.is_empty() {
+            break;
+        }
+        let seq = stats.total;
+        stats.total += 1;
+
+        let key = opts.key(Record::from_raw(&buf, &opts.delim).body)
+            .map(|key| key.into_owned());
+        let entry = Entry {
+            raw: buf,
+            count: 1,
+            position: seq,
+        };
+        match key {
+            Some(ref key) if excluded.contains(key) => stats.excluded += 1,
+            Some(key) => partitions.write(&Spilled::from_entry(key, entry), 0)?,
+            None if opts.unmatched == Unmatched::Pass => {
+                Spilled::from_entry(Vec::new(), entry).write_to(&mut passed_out)?;
+                passed_count += 1;
+            }
+            None => stats.dropped += 1,
+        }
+    }
+    passed_out.flush()?;
+
+    for path in partitions.finish()? {
+        spill.dedup_partition(&path, 1)?;
+    }
+    stats.unique = spill.unique + passed_count;
+
+    if !opts.count_only {
+        spill.runs.push(passed_path);
+        spill.write_runs(out)?;
+    }
+    Ok(stats)
+}
+
+/// The state of a run that has spilled to disk.
+struct Spill<'o> {
+    dir: SpillDir,
+    opts: &'o Options,
+    /// Files holding unique entries in input order, to be merged into the output.
+    runs: Vec<PathBuf>,
+    /// The number of unique keys found so far.
+    unique: u64,
+}
+
+impl<'o> Spill<'o> {
+    fn create_partitions(&mut self) -> io::Result<Partitions> {
+        let mut partitions = Partitions {
+            paths: Vec::with_capacity(FANOUT),
+            files: Vec::with_capacity(FANOUT),
+        };
+        for _ in 0..FANOUT {
+            let (path, file) = self.dir.create_file()?;
+            partitions.paths.push(path);
+            partitions.files.push(file);
+        }
+        Ok(partitions)
+    }
+
+    /// Deduplicates the entries of the partition at `path` in memory, adding a run of its unique
+    /// entries, or splits it into smaller partitions if it doesn't fit in memory.
+    fn dedup_partition(&mut self, path: &Path, depth: u32) -> io::Result<()> {
+        let max_memory = self.opts.max_memory.unwrap_or(usize::max_value());
+        let mut unique: Map<Vec<u8>, Spilled> = Map::default();
+        let mut usage = 0;
+        let mut input = BufReader::new(File::open(path)?);
+        while let Some(mut entry) = Spilled::read_from(&mut input)? {
+            if let Some(seen) = unique.get_mut(&entry.key) {
+                seen.merge(entry, self.opts.keep);
+                continue;
+            }
+            usage += entry.key.len() + entry.raw.len() + ENTRY_OVERHEAD;
+            if usage > max_memory && depth < MAX_DEPTH {
+                return self.split_partition(path, depth);
+            }
+            let key = mem::replace(&mut entry.key, Vec::new());
+            unique.insert(key, entry);
+        }
+        fs::remove_file(path)?;
+        self.unique += unique.len() as u64;
+
+        if self.opts.count_only {
+            return Ok(());
+        }
+        let mut unique: Vec<Spilled> = unique.into_iter().map(|(_, entry)| entry).collect();
+        unique.sort_unstable_by_key(|entry| entry.seq);
+        let (run_path, mut run) = self.dir.create_file()?;
+        for entry in &unique {
+            entry.write_to(&mut run)?;
+        }
+        run.flush()?;
+        self.runs.push(run_path);
+        Ok(())
+    }
+
+    fn split_partition(&mut self, path: &Path, depth: u32) -> io::Result<()> {
+        let mut partitions = self.create_partitions()?;
+        let mut input = BufReader::new(File::open(path)?);
+        while let Some(entry) = Spilled::read_from(&mut input)? {
+            partitions.write(&entry, depth)?;
+        }
+        fs::remove_file(path)?;
+        for path in partitions.finish()? {
+            self.dedup_partition(&path, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Merges every run and writes the entries that pass the filter to `out`.
+    fn write_runs<W: io::Write>(&mut self, out: &mut W) -> io::Result<()> {
+        while self.runs.len() > MAX_MERGE {
+            let batch: Vec<PathBuf> = self.runs.drain(..MAX_MERGE).collect();
+            let (path, mut merged) = self.dir.create_file()?;
+            merge(&batch, |entry| entry.write_to(&mut merged))?;
+            merged.flush()?;
+            self.runs.push(path);
+        }
+
+        let opts = self.opts;
+        merge(&self.runs, |entry| {
+            if opts.filter.matches(entry.count) {
+                let record = Record::from_raw(&entry.raw, &opts.delim);
+                record.write_counted_to(out, entry.count, opts)
+            } else {
+                Ok(())
+            }
+        })
+    }
+}
+
+/// A set of partition files being written to.
+struct Partitions {
+    paths: Vec<PathBuf>,
+    files: Vec<BufWriter<File>>,
+}
+
+impl Partitions {
+    /// Writes `entry` to the partition its key hashes to. Each `depth` of partitioning hashes
+    /// keys differently, so that a partition can be split further.
+    fn write(&mut self, entry: &Spilled, depth: u32) -> io::Result<()> {
+        let mut hasher = FxHasher::default();
+        hasher.write_u32(depth);
+        hasher.write(&entry.key);
+        let partition = (hasher.finish() >> 32) as usize % FANOUT;
+        entry.write_to(&mut self.files[partition])
+    }
+
+    fn finish(self) -> io::Result<Vec<PathBuf>> {
+        for mut file in self.files {
+            file.flush()?;
+        }
+        Ok(self.paths)
+    }
+}
+
+/// Merges `runs`, each of which holds entries in input order, passing every entry to `f` in
+/// input order. The runs are removed afterwards.
+fn merge<F>(runs: &[PathBuf], mut f: F) -> io::Result<()>
+where
+    F: FnMut(Spilled) -> io::Result<()>,
+{
+    let mut inputs = Vec::with_capacity(runs.len());
+    let mut heads = Vec::with_capacity(runs.len());
+    let mut order = BinaryHeap::with_capacity(runs.len());
+    for (i, path) in runs.iter().enumerate() {
+        let mut input = BufReader::new(File::open(path)?);
+        let head = Spilled::read_from(&mut input)?;
+        if let Some(ref entry) = head {
+            order.push(Reverse((entry.seq, i)));
+        }
+        inputs.push(input);
+        heads.push(head);
+    }
+
+    while let Some(Reverse((_, i))) = order.pop() {
+        let next = Spilled::read_from(&mut inputs[i])?;
+        if let Some(ref entry) = next {
+            order.push(Reverse((entry.seq, i)));
+        }
+        let entry = mem::replace(&mut heads[i], next).unwrap();
+        f(entry)?;
+    }
+
+    for path in runs {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// An entry as written to a spill file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Spilled {
+    /// The position of the entry in the input.
+    seq: u64,
+    count: u64,
+    /// The key of the entry. This is left empty in runs, which no longer need it.
+    key: Vec<u8>,
+    raw: Vec<u8>,
+}
+
+impl Spilled {
+    fn from_entry(key: Vec<u8>, entry: Entry) -> Self {
+        Spilled {
+            seq: entry.position,
+            count: entry.count,
+            key,
+            raw: entry.raw,
+        }
+    }
+
+    /// Combines a later-read occurrence of the same key into this one.
+    fn merge(&mut self, other: Spilled, keep: Keep) {
+        self.count += other.count;
+        let later = other.seq > self.seq;
+        if later == (keep == Keep::Last) {
+            self.seq = other.seq;
+            self.raw = other.raw;
+        }
+    }
+
+    fn write_to<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.seq.to_le_bytes())?;
+        out.write_all(&self.count.to_le_bytes())?;
+        out.write_all(&(self.key.len() as u64).to_le_bytes())?;
+        out.write_all(&self.key)?;
+        out.write_all(&(self.raw.len() as u64).to_le_bytes())?;
+        out.write_all(&self.raw)
+    }
+
+    /// Reads the next entry from `input`, or `None` at the end of the file.
+    fn read_from<R: io::Read>(input: &mut R) -> io::Result<Option<Self>> {
+        let mut seq = [0; 8];
+        // The end of the file can only fall between entries.
+        if input.read(&mut seq[..1])? == 0 {
+            return Ok(None);
+        }
+        input.read_exact(&mut seq[1..])?;
+        let count = read_u64(input)?;
+        let key = read_bytes(input)?;
+        let raw = read_bytes(input)?;
+        Ok(Some(Spilled {
+            seq: u64::from_le_bytes(seq),
+            count,
+            key,
+            raw,
+        }))
+    }
+}
+
+fn read_u64<R: io::Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes<R: io::Read>(input: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u64(input)?;
+    let mut buf = Vec::with_capacity(len as usize);
+    input.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated spill file"));
+    }
+    Ok(buf)
+}
+
+/// A temporary directory holding spill files, which is removed along with them when dropped.
+struct SpillDir {
+    path: PathBuf,
+    files: u64,
+}
+
+impl SpillDir {
+    /// Creates a new, uniquely named directory within `parent`, or within the system's temporary
+    /// directory if there is no parent.
+    fn create(parent: Option<&PathBuf>) -> io::Result<Self> {
+        let parent = parent.cloned().unwrap_or_else(env::temp_dir);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        for attempt in 0.. {
+            let name = format!("dedup-spill-{}-{}-{}", process::id(), nanos, attempt);
+            let path = parent.join(name);
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(SpillDir { path, files: 0 }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+
+    fn create_file(&mut self) -> io::Result<(PathBuf, BufWriter<File>)> {
+        let path = self.path.join(self.files.to_string());
+        self.files += 1;
+        let file = File::create(&path)?;
+        Ok((path, BufWriter::new(file)))
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spilled_round_trip() {
+        let entries = vec![
+            Spilled {
+                seq: 3,
+                count: 2,
+                key: b"ham".to_vec(),
+                raw: b"HAM\n".to_vec(),
+            },
+            Spilled {
+                seq: 7,
+                count: 1,
+                key: Vec::new(),
+                raw: Vec::new(),
+            },
+        ];
+        let mut buf = Vec::new();
+        for entry in &entries {
+            entry.write_to(&mut buf).unwrap();
+        }
+
+        let mut input = &buf[..];
+        assert_eq!(Spilled::read_from(&mut input).unwrap().as_ref(), Some(&entries[0]));
+        assert_eq!(Spilled::read_from(&mut input).unwrap().as_ref(), Some(&entries[1]));
+        assert_eq!(Spilled::read_from(&mut input).unwrap(), None);
+
+        let mut truncated = &buf[..buf.len() - 1];
+        Spilled::read_from(&mut truncated).unwrap();
+        assert!(Spilled::read_from(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn spill_dir_is_removed() {
+        let path = {
+            let mut dir = SpillDir::create(None).unwrap();
+            let (file, mut out) = dir.create_file().unwrap();
+            out.write_all(b"spam").unwrap();
+            assert!(file.exists());
+            dir.path.clone()
+        };
+        assert!(!path.exists());
+    }
+}
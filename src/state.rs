@@ -0,0 +1,221 @@
+use siphasher::sip::SipHasher13;
+
+use error::DedupError;
+use set::FingerprintSet;
+
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The bytes every state file starts with.
+const MAGIC: &[u8; 8] = b"DEDUPFP\0";
+/// The version of the state file format written by this crate.
+const VERSION: u32 = 1;
+
+/// Saving and loading state files, which hold the fingerprints of the entries seen by earlier
+/// runs so that they can be suppressed by later ones.
+///
+/// A state file holds the magic bytes `DEDUPFP\0`, a little-endian `u32` format version, a `u64`
+/// count of fingerprints, each fingerprint as a `u128`, and finally a `u64` SipHash-1-3 checksum
+/// of everything before it.
+impl FingerprintSet {
+    /// Loads the state file at `path`.
+    ///
+    /// Fails with `DedupError::CorruptState` if the file is not a state file, has an unsupported
+    /// version, or is truncated or otherwise damaged.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, DedupError> {
+        FingerprintSet::read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Saves the set to a state file at `path`. The file is written in full to a temporary file
+    /// alongside it before being renamed into place, so a failed save leaves `path` untouched.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let written = File::create(&tmp).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            self.write_to(&mut out)?;
+            let file = out.into_inner()?;
+            file.sync_all()
+        });
+        match written.and_then(|_| fs::rename(&tmp, path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+
+    /// Reads a set in the state file format from `input`.
+    pub fn read_from<R: Read>(input: R) -> Result<Self, DedupError> {
+        let mut input = Checksummed::new(input);
+        let mut magic = [0; 8];
+        read_exact(&mut input, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(DedupError::CorruptState("not a state file".to_string()));
+        }
+        let version = read_u32(&mut input)?;
+        if version != VERSION {
+            return Err(DedupError::CorruptState(format!(
+                "unsupported version {}, expected {}",
+                version, VERSION
+            )));
+        }
+
+        let count = read_u64(&mut input)?;
+        // The count can't be trusted until the checksum has been verified.
+        let mut set = FingerprintSet::with_capacity(count.min(1 << 20) as usize);
+        let mut buf = [0; 16];
+        for _ in 0..count {
+            read_exact(&mut input, &mut buf)?;
+            set.extend(Some(u128::from_le_bytes(buf)));
+        }
+
+        let checksum = input.checksum();
+        let mut input = input.into_inner();
+        if read_u64(&mut input)? != checksum {
+            return Err(DedupError::CorruptState("checksum mismatch".to_string()));
+        }
+        if input.read(&mut [0])? != 0 {
+            return Err(DedupError::CorruptState("unexpected data at the end".to_string()));
+        }
+        Ok(set)
+    }
+
+    /// Writes the set in the state file format to `out`.
+    pub fn write_to<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut out = Checksummed::new(out);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&(self.len() as u64).to_le_bytes())?;
+        for fingerprint in self.iter() {
+            out.write_all(&fingerprint.to_le_bytes())?;
+        }
+        let checksum = out.checksum();
+        out.into_inner().write_all(&checksum.to_le_bytes())
+    }
+}
+
+/// Reads exactly enough bytes to fill `buf`, reporting a file that ends early as corrupt.
+fn read_exact<R: Read>(input: &mut R, buf: &mut [u8]) -> Result<(), DedupError> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => DedupError::CorruptState("truncated".to_string()),
+        _ => DedupError::from(e),
+    })
+}
+
+fn read_u32<R: Read>(input: &mut R) -> Result<u32, DedupError> {
+    let mut buf = [0; 4];
+    read_exact(input, &mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> Result<u64, DedupError> {
+    let mut buf = [0; 8];
+    read_exact(input, &mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// A reader or writer that checksums every byte passing through it.
+struct Checksummed<T> {
+    inner: T,
+    hasher: SipHasher13,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Self {
+        Checksummed {
+            inner,
+            hasher: SipHasher13::new(),
+        }
+    }
+
+    fn checksum(&self) -> u64 {
+        self.hasher.finish()
+    }
+
+    fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn state() -> FingerprintSet {
+        let mut state = FingerprintSet::new();
+        for key in &["spam", "ham", "eggs"] {
+            state.insert(key.as_bytes());
+        }
+        state
+    }
+
+    fn is_corrupt(buf: &[u8]) -> bool {
+        matches!(FingerprintSet::read_from(buf), Err(DedupError::CorruptState(_)))
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut buf = Vec::new();
+        state().write_to(&mut buf).unwrap();
+        assert_eq!(buf.len(), 8 + 4 + 8 + 3 * 16 + 8);
+
+        let loaded = FingerprintSet::read_from(&buf[..]).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert!(loaded.contains(b"ham"));
+        assert!(!loaded.contains(b"bacon"));
+    }
+
+    #[test]
+    fn corrupt_state() {
+        let mut buf = Vec::new();
+        state().write_to(&mut buf).unwrap();
+
+        assert!(is_corrupt(b"spam\nham\n"));
+        assert!(is_corrupt(&buf[..buf.len() - 1]));
+        assert!(is_corrupt(&[&buf[..], b"\0"].concat()));
+
+        let mut flipped = buf.clone();
+        flipped[30] ^= 1;
+        assert!(is_corrupt(&flipped));
+
+        let mut future = buf.clone();
+        future[8] = 2;
+        assert!(is_corrupt(&future));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("dedup-state-test-{}", process::id()));
+        state().save(&path).unwrap();
+        let loaded = FingerprintSet::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 3);
+    }
+}
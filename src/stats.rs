@@ -0,0 +1,47 @@
+use std::fmt::{Display, Error, Formatter};
+
+/// Statistics about a completed deduplication run. Fields may be added in later releases.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of entries read from the input.
+    pub total: u64,
+    /// The number of distinct entries found.
+    pub unique: u64,
+    /// The number of entries discarded because no key could be extracted from them.
+    pub dropped: u64,
+    /// The number of entries discarded because they occur in an exclusion input.
+    pub excluded: u64,
+    /// The approximate number of bytes saved by remembering fingerprints rather than keys, as
+    /// [`Options::hash_only`](struct.Options.html#structfield.hash_only) does.
+    pub bytes_saved: u64,
+    /// The number of keys forgotten to stay within
+    /// [`Options::max_entries`](struct.Options.html#structfield.max_entries).
+    pub evictions: u64,
+    /// Keeps `Stats` from being built outside this crate, so that fields can be added.
+    pub(crate) _private: (),
+}
+
+impl Stats {
+    /// The number of entries that were discarded as duplicates of an earlier entry.
+    pub fn duplicates(&self) -> u64 {
+        self.total - self.unique - self.dropped - self.excluded
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        writeln!(f, "unique: {}", self.unique)?;
+        writeln!(f, "total: {}", self.total)?;
+        writeln!(f, "duplicates: {}", self.duplicates())?;
+        if self.dropped > 0 {
+            writeln!(f, "dropped: {}", self.dropped)?;
+        }
+        if self.excluded > 0 {
+            writeln!(f, "excluded: {}", self.excluded)?;
+        }
+        if self.evictions > 0 {
+            writeln!(f, "evictions: {}", self.evictions)?;
+        }
+        Ok(())
+    }
+}
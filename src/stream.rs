@@ -1,50 +1,368 @@
-use args::Options;
+use buffer::BufferDeduper;
+use key::Unmatched;
+use options::{Filter, Keep, Options, SetOperation, Window};
 use error::DedupError;
-use set::Set;
+use records::{Record, Splitter};
+use set::{BloomFilter, ExpiringSet, FingerprintSet, KeySet, LruSet, Map, Set};
+use spill::{self, ENTRY_OVERHEAD};
+use stats::Stats;
 
+use std::borrow::Cow;
 use std::io;
+use std::mem;
 
-
+/// Deduplicates the entries of a stream that cannot be held in memory all at once.
+///
+/// Each unique entry is copied into the set of seen entries as it is read, or only its fingerprint
+/// if [`Options::hash_only`](struct.Options.html#structfield.hash_only) is set. If
+/// [`Options::max_memory`](struct.Options.html#structfield.max_memory) is set, entries are spilled
+/// to temporary files once they no longer fit.
 pub struct UnsortedStreamDeduper<R: io::BufRead, W: io::Write> {
     input: R,
     opts: Options,
     out: W,
-    dup_store: Set<Vec<u8>>,
+    dup_store: Box<dyn KeySet<Vec<u8>>>,
+    excluded: Set<Vec<u8>>,
+    /// The keys seen by earlier runs, which replaces `dup_store` while `run_with_state` runs.
+    state: Option<FingerprintSet>,
 }
 
 impl<R: io::BufRead, W: io::Write> UnsortedStreamDeduper<R, W> {
+    /// Creates a new `UnsortedStreamDeduper` reading entries from `input` and writing to `output`.
     pub fn new(input: R, output: W, options: Options) -> Self {
+        let dup_store: Box<dyn KeySet<Vec<u8>>> = match options.bloom_filter {
+            Some(config) => Box::new(BloomFilter::new(config)),
+            None if options.max_entries.is_some() => {
+                Box::new(LruSet::new(options.max_entries.unwrap()))
+            }
+            None if options.hash_only => Box::new(FingerprintSet::with_capacity(1024)),
+            None => Box::new(Set::with_capacity_and_hasher(1024, Default::default())),
+        };
         UnsortedStreamDeduper {
             input,
             opts: options,
             out: output,
-            dup_store: Set::with_capacity_and_hasher(1024, Default::default()),
+            dup_store,
+            excluded: Set::default(),
+            state: None,
         }
     }
 
-    pub fn run(mut self) -> Result<u64, DedupError> {
-        let delim = self.opts.delim;
-        let mut count: u64 = 0;
+    /// Excludes every entry of `buffer` from the output, as though each had already been written.
+    /// Entries are compared by the same key as the input, and are not counted in the statistics.
+    pub fn exclude(&mut self, buffer: &[u8]) {
+        let splitter = Splitter::new(&self.opts);
+        for record in splitter.split(buffer) {
+            if let Some(key) = self.opts.key(record.body) {
+                self.excluded.insert(key.into_owned());
+            }
+        }
+    }
+
+    /// Writes each unique entry to the output, returning statistics about the run.
+    pub fn run(mut self) -> Result<Stats, DedupError> {
+        self.run_all()
+    }
+
+    /// Like `run`, but entries whose keys are in `state`, such as those seen by earlier runs, are
+    /// never written, as though they had already been seen. The key of every entry read that is
+    /// not excluded is added to `state`, whether or not the entry is written.
+    ///
+    /// The state is held in memory, so `max_memory` is not applied. Returns an error if
+    /// `Options::window` is set, since a state never forgets a key.
+    pub fn run_with_state(mut self, state: &mut FingerprintSet) -> Result<Stats, DedupError> {
+        if self.opts.window.is_some() {
+            return Err(DedupError::ArgumentParseError(
+                "A window can't be used along with a state, which never forgets a key".to_string(),
+            ));
+        }
+        self.state = Some(mem::replace(state, Default::default()));
+        let stats = self.run_all();
+        *state = self.state.take().unwrap_or_default();
+        stats
+    }
+
+    fn run_all(&mut self) -> Result<Stats, DedupError> {
+        if let Some(window) = self.opts.window {
+            if let Some(option) = window_conflict(&self.opts) {
+                return Err(DedupError::ArgumentParseError(format!(
+                    "The {} option can't be used along with a window",
+                    option
+                )));
+            }
+            return self.run_windowed(window);
+        }
+        if self.opts.record_start.is_some() {
+            // Entries can't be told apart without looking ahead to where the next one starts.
+            let mut buffer = Vec::new();
+            self.input.read_to_end(&mut buffer)?;
+            let mut dedup = BufferDeduper::new(&buffer, &mut self.out, self.opts.clone());
+            dedup.exclude_keys(mem::replace(&mut self.excluded, Default::default()));
+            return match self.state {
+                Some(ref mut state) => dedup.run_with_state(state),
+                None => dedup.run(),
+            };
+        }
+        let needs_buffering = self.opts.needs_counts() || self.opts.keep == Keep::Last;
+        if needs_buffering || self.opts.max_memory.is_some() {
+            return self.run_buffered();
+        }
+
+        let delim = self.opts.delim.clone();
+        let dup_store: &mut dyn KeySet<Vec<u8>> = match self.state {
+            Some(ref mut state) => state,
+            None => &mut *self.dup_store,
+        };
+        let mut stats = Stats::default();
 
         loop {
             let mut buf = Vec::new();
-            self.input.read_until(delim, &mut buf)?;
+            read_record(&mut self.input, &delim, &mut buf)?;
             if buf.is_empty() {
-                return Ok(count);
+                stats.evictions = dup_store.evictions();
+                return Ok(stats);
             }
+            stats.total += 1;
 
-            if !self.dup_store.contains(&buf) {
-                self.out.write_all(&buf)?;
-                self.dup_store.insert(buf);
-                count += 1;
+            let (key, body_len) = {
+                let record = Record::from_raw(&buf, &delim);
+                let key = match self.opts.key(record.body) {
+                    Some(key) => key,
+                    None if self.opts.unmatched == Unmatched::Pass => {
+                        if !self.opts.count_only {
+                            record.write_to(&mut self.out, &self.opts)?;
+                        }
+                        stats.unique += 1;
+                        continue;
+                    }
+                    None => {
+                        stats.dropped += 1;
+                        continue;
+                    }
+                };
+                if self.excluded.contains(&key[..]) {
+                    stats.excluded += 1;
+                    continue;
+                }
+                if dup_store.touch(&key[..]) {
+                    continue;
+                }
+                if !self.opts.count_only {
+                    record.write_to(&mut self.out, &self.opts)?;
+                }
+                // When the key is the whole entry, the entry's own buffer is stored instead.
+                let key = match key {
+                    Cow::Borrowed(k) if k.len() == record.body.len() => None,
+                    k => Some(k.into_owned()),
+                };
+                (key, record.body.len())
+            };
+            let key = key.unwrap_or_else(|| {
+                buf.truncate(body_len);
+                buf
+            });
+            stats.bytes_saved += dup_store.bytes_saved(&key) as u64;
+            dup_store.insert(key);
+            stats.unique += 1;
+        }
+    }
+
+    /// Writes each entry that was not seen within `window`, forgetting entries as the window
+    /// passes. The output is flushed after each entry written, so that a live stream can be
+    /// followed.
+    fn run_windowed(&mut self, window: Window) -> Result<Stats, DedupError> {
+        let delim = self.opts.delim.clone();
+        let mut recent = ExpiringSet::new(window);
+        let mut stats = Stats::default();
+
+        loop {
+            let mut buf = Vec::new();
+            read_record(&mut self.input, &delim, &mut buf)?;
+            if buf.is_empty() {
+                return Ok(stats);
+            }
+            stats.total += 1;
+
+            let record = Record::from_raw(&buf, &delim);
+            let unique = match self.opts.key(record.body) {
+                Some(ref key) if self.excluded.contains(&key[..]) => {
+                    recent.skip();
+                    stats.excluded += 1;
+                    continue;
+                }
+                Some(key) => recent.insert(&key),
+                None if self.opts.unmatched == Unmatched::Pass => {
+                    recent.skip();
+                    true
+                }
+                None => {
+                    recent.skip();
+                    stats.dropped += 1;
+                    continue;
+                }
+            };
+            if unique {
+                stats.unique += 1;
+                if !self.opts.count_only {
+                    record.write_to(&mut self.out, &self.opts)?;
+                    self.out.flush()?;
+                }
             }
         }
     }
+
+    /// Keeps every unique entry in memory until the input is exhausted, for modes where no entry
+    /// can be written before the whole input has been seen, or until they outgrow `max_memory`.
+    fn run_buffered(&mut self) -> Result<Stats, DedupError> {
+        let delim = self.opts.delim.clone();
+        let keep_last = self.opts.keep == Keep::Last;
+        let max_memory = match self.opts.max_memory {
+            Some(max_memory) if self.state.is_none() => max_memory,
+            _ => usize::max_value(),
+        };
+        let mut usage = 0;
+        let mut stats = Stats::default();
+        let mut entries: Map<Vec<u8>, Entry> = Map::default();
+        let mut passed: Vec<Entry> = Vec::new();
+
+        loop {
+            if usage > max_memory {
+                let (input, out, opts) = (&mut self.input, &mut self.out, &self.opts);
+                return spill::run(input, out, opts, &self.excluded, entries, passed, stats);
+            }
+            let mut buf = Vec::new();
+            read_record(&mut self.input, &delim, &mut buf)?;
+            if buf.is_empty() {
+                break;
+            }
+            let position = stats.total;
+            stats.total += 1;
+
+            let key = self.opts
+                .key(Record::from_raw(&buf, &delim).body)
+                .map(Cow::into_owned);
+            let key = match key {
+                Some(ref key) if self.excluded.contains(key) => {
+                    stats.excluded += 1;
+                    continue;
+                }
+                Some(ref key) if self.seen_before(key) => continue,
+                Some(key) => key,
+                None if self.opts.unmatched == Unmatched::Pass => {
+                    usage += buf.len() + ENTRY_OVERHEAD;
+                    passed.push(Entry {
+                        raw: buf,
+                        count: 1,
+                        position,
+                    });
+                    continue;
+                }
+                None => {
+                    stats.dropped += 1;
+                    continue;
+                }
+            };
+            if let Some(entry) = entries.get_mut(&key) {
+                entry.count += 1;
+                if keep_last {
+                    usage = usage + buf.len() - entry.raw.len();
+                    entry.position = position;
+                    entry.raw = buf;
+                }
+                continue;
+            }
+            usage += key.len() + buf.len() + ENTRY_OVERHEAD;
+            let entry = Entry {
+                raw: buf,
+                count: 1,
+                position,
+            };
+            entries.insert(key, entry);
+        }
+        stats.unique = (entries.len() + passed.len()) as u64;
+        if let Some(ref mut state) = self.state {
+            for key in entries.keys() {
+                state.insert(key);
+            }
+        }
+
+        if self.opts.count_only {
+            return Ok(stats);
+        }
+
+        let mut entries: Vec<Entry> = entries
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .chain(passed)
+            .collect();
+        entries.sort_unstable_by_key(|entry| entry.position);
+        for entry in entries {
+            if self.opts.filter.matches(entry.count) {
+                let record = Record::from_raw(&entry.raw, &delim);
+                record.write_counted_to(&mut self.out, entry.count, &self.opts)?;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Whether `key` is in the state of earlier runs, if there is one.
+    fn seen_before(&self, key: &[u8]) -> bool {
+        self.state.as_ref().map_or(false, |state| state.contains(key))
+    }
+}
+
+/// Reads the next entry into `buf`, including its delimiter if it has one. Like
+/// `BufRead::read_until`, but `delim` may be more than one byte long.
+pub fn read_record<R>(input: &mut R, delim: &[u8], buf: &mut Vec<u8>) -> io::Result<()>
+where
+    R: io::BufRead,
+{
+    let last = delim[delim.len() - 1];
+    while input.read_until(last, buf)? > 0 && !buf.ends_with(delim) {}
+    Ok(())
+}
+
+/// A unique entry held by `UnsortedStreamDeduper` until it can be written.
+pub struct Entry {
+    pub raw: Vec<u8>,
+    pub count: u64,
+    pub position: u64,
+}
+
+/// Returns the name of an option that a window would override, if one is set. Every entry is
+/// written as soon as it is read and keys are forgotten once the window passes, so nothing that
+/// needs the whole input or remembers keys in its own way can be honored.
+fn window_conflict(opts: &Options) -> Option<&'static str> {
+    if opts.record_start.is_some() {
+        Some("record_start")
+    } else if opts.occurrences.is_some() {
+        Some("occurrences")
+    } else if opts.filter != Filter::All {
+        Some("filter")
+    } else if opts.set_operation != SetOperation::Union {
+        Some("set_operation")
+    } else if opts.keep == Keep::Last {
+        Some("keep")
+    } else if opts.max_memory.is_some() {
+        Some("max_memory")
+    } else if opts.bloom_filter.is_some() {
+        Some("bloom_filter")
+    } else if opts.max_entries.is_some() {
+        Some("max_entries")
+    } else if opts.hash_only {
+        Some("hash_only")
+    } else {
+        None
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use options::{CountPosition, Filter, Keep};
+    use key::{KeyFields, KeyRegex, Unmatched, Whitespace};
+    use regex::bytes::Regex;
     use std::str;
     use std::io::BufReader;
 
@@ -76,4 +394,369 @@ ham eggs
         }
         assert_eq!(BREAKFAST_DEDUP, str::from_utf8(&output).unwrap());
     }
+
+    #[test]
+    fn stream_unterminated_final_entry() {
+        let mut output: Vec<u8> = Vec::new();
+        let reader = BufReader::new("spam\nham\neggs".as_bytes());
+        {
+            let opts = Options {
+                add_terminator: true,
+                ..Options::default()
+            };
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\nham\neggs\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn stream_unterminated_final_duplicate() {
+        let mut output: Vec<u8> = Vec::new();
+        let reader = BufReader::new("spam\nham\nspam".as_bytes());
+        {
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, Options::default());
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\nham\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn stream_breakfast_occurrences() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                occurrences: Some(CountPosition::Prefix),
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!(
+            "      2 spam\n      3 ham\n      2 eggs\n      1 ham eggs\n",
+            str::from_utf8(&output).unwrap()
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                occurrences: Some(CountPosition::Suffix),
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!(
+            "spam\t2\nham\t3\neggs\t2\nham eggs\t1\n",
+            str::from_utf8(&output).unwrap()
+        );
+    }
+
+    #[test]
+    fn stream_breakfast_filters() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                filter: Filter::Repeated,
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\nham\neggs\n", str::from_utf8(&output).unwrap());
+
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                filter: Filter::Unrepeated,
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("ham eggs\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn stream_breakfast_keep_last() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                keep: Keep::Last,
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("ham eggs\neggs\nham\nspam\n", str::from_utf8(&output).unwrap());
+
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                keep: Keep::Last,
+                occurrences: Some(CountPosition::Suffix),
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!(
+            "ham eggs\t1\neggs\t2\nham\t3\nspam\t2\n",
+            str::from_utf8(&output).unwrap()
+        );
+    }
+
+    static INVENTORY: &str = "\
+1,spam
+2,ham
+3,spam
+4,eggs
+5,ham
+";
+
+    #[test]
+    fn stream_key_field() {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                key_fields: KeyFields::new(vec![2], b','),
+                ..Options::default()
+            };
+            let reader = BufReader::new(INVENTORY.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("1,spam\n2,ham\n4,eggs\n", str::from_utf8(&output).unwrap());
+    }
+
+    static LOG: &str = "\
+12:00:01 login user=spam
+12:00:02 login user=ham
+12:00:03 restart
+12:00:04 login user=spam
+";
+
+    #[test]
+    fn stream_key_regex() {
+        let mut output: Vec<u8> = Vec::new();
+        let stats = {
+            let opts = Options {
+                key_regex: KeyRegex::new(Regex::new("user=(.*)").unwrap(), None).ok(),
+                ..Options::default()
+            };
+            let reader = BufReader::new(LOG.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap()
+        };
+        assert_eq!(
+            "12:00:01 login user=spam\n12:00:02 login user=ham\n12:00:03 restart\n",
+            str::from_utf8(&output).unwrap()
+        );
+        assert_eq!(stats.unique, 3);
+
+        let mut output: Vec<u8> = Vec::new();
+        let stats = {
+            let opts = Options {
+                key_regex: KeyRegex::new(Regex::new("user=(.*)").unwrap(), None).ok(),
+                unmatched: Unmatched::Drop,
+                keep: Keep::Last,
+                ..Options::default()
+            };
+            let reader = BufReader::new(LOG.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap()
+        };
+        assert_eq!(
+            "12:00:02 login user=ham\n12:00:04 login user=spam\n",
+            str::from_utf8(&output).unwrap()
+        );
+        assert_eq!(stats.dropped, 1);
+        assert_eq!(stats.duplicates(), 1);
+    }
+
+    #[test]
+    fn stream_whitespace_insensitive() {
+        let input = "spam  ham\r\n spam ham\n\tspam\tham \r\neggs\n";
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                whitespace: Whitespace {
+                    trim: true,
+                    collapse: true,
+                    ignore_trailing_cr: true,
+                },
+                ..Options::default()
+            };
+            let reader = BufReader::new(input.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam  ham\r\neggs\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn stream_multi_byte_delimiter() {
+        let input = "spam\n\nham\neggs\n\n\nspam\n\nham\neggs";
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let opts = Options {
+                delim: b"\n\n".to_vec(),
+                ..Options::default()
+            };
+            let reader = BufReader::new(input.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap();
+        }
+        assert_eq!("spam\n\nham\neggs\n\n\nspam\n\n", str::from_utf8(&output).unwrap());
+    }
+
+    #[test]
+    fn stream_exclude() {
+        let mut output: Vec<u8> = Vec::new();
+        let stats = {
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let mut dedup = UnsortedStreamDeduper::new(reader, &mut output, Options::default());
+            dedup.exclude(b"spam\neggs\n");
+            dedup.run().unwrap()
+        };
+        assert_eq!("ham\nham eggs\n", str::from_utf8(&output).unwrap());
+        assert_eq!(stats.excluded, 4);
+        assert_eq!(stats.duplicates(), 2);
+    }
+
+    #[test]
+    fn stream_hash_only() {
+        let mut output: Vec<u8> = Vec::new();
+        let stats = {
+            let opts = Options {
+                hash_only: true,
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap()
+        };
+        assert_eq!(BREAKFAST_DEDUP, str::from_utf8(&output).unwrap());
+        assert_eq!(stats.unique, 4);
+        assert_eq!(stats.bytes_saved, 19 + 4 * 8);
+
+        // A bounded set remembers whole keys, even if fingerprints were asked for.
+        let opts = Options {
+            hash_only: true,
+            max_entries: Some(2),
+            ..Options::default()
+        };
+        let reader = BufReader::new(BREAKFAST.as_bytes());
+        let stats = UnsortedStreamDeduper::new(reader, io::sink(), opts).run().unwrap();
+        assert_eq!(stats.bytes_saved, 0);
+    }
+
+    #[test]
+    fn stream_spill() {
+        let input: String = (0..2000).map(|i| format!("{}\n", (i * 7919) % 613)).collect();
+        let configs = vec![
+            Options::default(),
+            Options {
+                keep: Keep::Last,
+                occurrences: Some(CountPosition::Suffix),
+                ..Options::default()
+            },
+            Options {
+                filter: Filter::Unrepeated,
+                key_regex: KeyRegex::new(Regex::new("^1(.)").unwrap(), None).ok(),
+                ..Options::default()
+            },
+        ];
+        for opts in configs {
+            let run = |opts: Options| {
+                let mut output: Vec<u8> = Vec::new();
+                let stats = {
+                    let reader = BufReader::new(input.as_bytes());
+                    let mut dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+                    dedup.exclude(b"42\n");
+                    dedup.run().unwrap()
+                };
+                (output, stats)
+            };
+            let (expected, expected_stats) = run(opts.clone());
+            let (output, stats) = run(Options {
+                max_memory: Some(1000),
+                ..opts
+            });
+            assert_eq!(str::from_utf8(&expected), str::from_utf8(&output));
+            assert_eq!(expected_stats, stats);
+        }
+    }
+
+    #[test]
+    fn stream_window() {
+        let mut output: Vec<u8> = Vec::new();
+        let stats = {
+            let opts = Options {
+                window: Some(Window::Entries(3)),
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap()
+        };
+        assert_eq!("spam\nham\neggs\nham eggs\nspam\n", str::from_utf8(&output).unwrap());
+        assert_eq!(stats.unique, 5);
+        assert_eq!(stats.duplicates(), 3);
+    }
+
+    #[test]
+    fn stream_window_conflicts() {
+        let window = Some(Window::Entries(3));
+        let conflicting = vec![
+            Options {
+                record_start: Some(Regex::new("(?m)^s").unwrap()),
+                ..Options::default()
+            },
+            Options {
+                keep: Keep::Last,
+                ..Options::default()
+            },
+            Options {
+                max_entries: Some(3),
+                ..Options::default()
+            },
+            Options {
+                hash_only: true,
+                ..Options::default()
+            },
+        ];
+        for opts in conflicting {
+            let opts = Options { window, ..opts };
+            let mut output: Vec<u8> = Vec::new();
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            assert!(dedup.run().is_err());
+            assert!(output.is_empty());
+        }
+    }
+
+    #[test]
+    fn stream_max_entries() {
+        let mut output: Vec<u8> = Vec::new();
+        let stats = {
+            let opts = Options {
+                max_entries: Some(3),
+                ..Options::default()
+            };
+            let reader = BufReader::new(BREAKFAST.as_bytes());
+            let dedup = UnsortedStreamDeduper::new(reader, &mut output, opts);
+            dedup.run().unwrap()
+        };
+        // Seeing eggs and ham again keeps them, so spam is forgotten rather than eggs.
+        assert_eq!("spam\nham\neggs\nham eggs\nspam\n", str::from_utf8(&output).unwrap());
+        assert_eq!(stats.unique, 5);
+        assert_eq!(stats.evictions, 2);
+    }
 }